@@ -1,13 +1,48 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
+use chrono::{Local, TimeZone};
 use prettytable::Table;
 use prettytable::row::Row;
 use prettytable::cell::Cell;
 use prettytable::format;
+use serde_json::Value;
+use term_size;
 
+use output;
+use output::Tabular;
+
+/// A branch as returned by the branches endpoints
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Branch {
+    id: String,
+    displayId: String,
+    latestCommit: Option<String>,
+    #[serde(default)]
+    isDefault: bool,
+}
+
+impl Branch {
+    pub fn name(&self) -> &str {
+        &self.displayId
+    }
+
+    /// The id of the commit the branch points at
+    pub fn head(&self) -> Option<&str> {
+        self.latestCommit.as_ref().map(|c| c.as_str())
+    }
+
+    pub fn is_default(&self) -> bool {
+        self.isDefault
+    }
+}
+
+#[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Reference {
     id: String,
+    #[serde(skip_serializing)]
+    displayId: Option<String>,
     pub repository: Repository,
 }
 
@@ -15,18 +50,53 @@ impl Reference {
     pub fn new(branch: String, slug: String, project: String) -> Reference {
         Reference {
             id: format!("refs/heads/{}", branch),
+            displayId: None,
             repository: Repository {
                 slug: slug,
                 project: Project { key: project },
+                links: HashMap::new(),
             },
         }
     }
+
+    /// The branch name, without the refs/heads/ prefix
+    pub fn branch(&self) -> String {
+        match self.displayId {
+            Some(ref display_id) => display_id.clone(),
+            None => self.id.trim_left_matches("refs/heads/").to_string(),
+        }
+    }
+
+    pub fn display(&self) -> String {
+        let branch = self.displayId.clone().unwrap_or(self.id.clone());
+        format!(
+            "{}/{}:{}",
+            self.repository.project.key, self.repository.slug, branch
+        )
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Repository {
     pub slug: String,
     pub project: Project,
+    #[serde(default, skip_serializing)]
+    links: HashMap<String, Vec<Link>>,
+}
+
+impl Repository {
+    /// Returns the ssh or http clone url of the repository
+    pub fn clone_url(&self, ssh: bool) -> Option<String> {
+        let wanted = if ssh { "ssh" } else { "http" };
+        if let Some(links) = self.links.get("clone") {
+            for link in links {
+                if link.name.as_ref().map(|n| n == wanted).unwrap_or(false) {
+                    return Some(link.href.clone());
+                }
+            }
+        }
+        None
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -42,11 +112,45 @@ pub struct User {
     displayName: Option<String>,
     #[serde(skip_serializing)]
     slug: Option<String>,
+    #[serde(skip_serializing)]
+    active: Option<bool>,
+}
+
+impl User {
+    pub fn new(name: &str) -> User {
+        User {
+            name: name.to_string(),
+            displayName: None,
+            slug: None,
+            active: None,
+        }
+    }
+
+    /// Users the server doesn't say anything about are assumed to be active
+    pub fn is_active(&self) -> bool {
+        self.active.unwrap_or(true)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn slug(&self) -> Option<&str> {
+        self.slug.as_ref().map(|s| s.as_str())
+    }
+
+    pub fn display_name(&self) -> String {
+        self.displayName.clone().unwrap_or(self.name.clone())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Reviewer {
     user: User,
+    #[serde(skip_serializing)]
+    approved: Option<bool>,
+    #[serde(skip_serializing)]
+    status: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -54,69 +158,126 @@ pub struct Author {
     user: User,
 }
 
+/// A user's review state on a pull request, as sent to and returned from the
+/// participants endpoint
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Participant {
+    user: User,
+    approved: bool,
+    status: String,
+}
+
+impl Participant {
+    pub fn new(name: &str, status: &str) -> Participant {
+        Participant {
+            user: User::new(name),
+            approved: status == "APPROVED",
+            status: status.to_string(),
+        }
+    }
+
+    pub fn status(&self) -> &str {
+        &self.status
+    }
+}
+
+/// A single page of one of bitbucket server's paged list responses
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Page<T> {
+    pub values: Vec<T>,
+    #[serde(default)]
+    pub isLastPage: bool,
+    pub nextPageStart: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct UserSearchResult {
     values: Vec<User>,
 }
 
+fn format_timestamp(millis: u64) -> String {
+    Local
+        .timestamp((millis / 1000) as i64, 0)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
 impl UserSearchResult {
-    pub fn print_tty(&self, force_colorize: bool) {
-        let mut table = Table::new();
+    pub fn new(values: Vec<User>) -> UserSearchResult {
+        UserSearchResult { values: values }
+    }
 
-        let format = format::FormatBuilder::new()
-            .padding(1, 1)
-            .separator(
-                format::LinePosition::Title,
-                format::LineSeparator::new('-', '-', '-', '-'),
-            )
-            .build();
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
 
-        table.set_format(format);
-        table.set_titles(Row::new(vec![Cell::new("name"), Cell::new("slug")]));
+    /// User names are case insensitive on the server
+    pub fn find_by_name(&self, name: &str) -> Option<&User> {
+        self.values.iter().find(|user| user.name.eq_ignore_ascii_case(name))
+    }
 
-        for user in &self.values {
-            let display_name = user.displayName
-                .clone()
-                .unwrap_or("missing display name".to_string());
-            let slug = user.slug.clone().unwrap_or("missing slug".to_string());
-            table.add_row(Row::new(vec![Cell::new(&display_name), Cell::new(&slug)]));
-        }
+    pub fn users(&self) -> &[User] {
+        &self.values
+    }
+}
 
-        table.print_tty(force_colorize);
+impl Tabular for UserSearchResult {
+    fn fields(&self) -> Vec<&'static str> {
+        vec!["name", "display_name", "slug"]
     }
 
-    pub fn is_empty(&self) -> bool {
-        self.values.is_empty()
+    fn records(&self) -> Vec<Vec<Value>> {
+        self.values
+            .iter()
+            .map(|user| {
+                vec![
+                    Value::from(user.name.clone()),
+                    output::optional(user.displayName.clone()),
+                    output::optional(user.slug.clone()),
+                ]
+            })
+            .collect()
     }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Link {
     href: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
 }
 
 #[allow(non_snake_case)]
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct PullRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<u64>,
     title: String,
     fromRef: Option<Reference>,
     toRef: Option<Reference>,
     reviewers: Vec<Reviewer>,
-    #[serde(default = "missing_description")]
+    #[serde(default)]
     description: String,
     #[serde(skip_serializing)]
     links: HashMap<String, Vec<Link>>,
     #[serde(skip_serializing)]
     author: Option<Author>,
-}
-
-fn missing_description() -> String {
-    "missing description".to_string()
+    #[serde(skip_serializing)]
+    state: Option<String>,
+    #[serde(skip_serializing)]
+    createdDate: Option<u64>,
+    #[serde(skip_serializing)]
+    updatedDate: Option<u64>,
 }
 
 impl PullRequest {
     pub fn new(title: &str) -> PullRequest {
         PullRequest {
+            id: None,
+            version: None,
             title: title.to_string(),
             fromRef: None,
             toRef: None,
@@ -124,6 +285,9 @@ impl PullRequest {
             description: String::new(),
             links: HashMap::new(),
             author: None,
+            state: None,
+            createdDate: None,
+            updatedDate: None,
         }
     }
 
@@ -161,11 +325,9 @@ impl PullRequest {
     {
         for reviewer in reviewers {
             let reviewer = Reviewer {
-                user: User {
-                    name: reviewer.to_string(),
-                    displayName: None,
-                    slug: None,
-                },
+                user: User::new(reviewer),
+                approved: None,
+                status: None,
             };
             self.reviewers.push(reviewer);
         }
@@ -177,6 +339,49 @@ impl PullRequest {
         self
     }
 
+    pub fn title<'a>(&'a mut self, title: &str) -> &'a mut PullRequest {
+        self.title = title.to_string();
+        self
+    }
+
+    /// Points the pull request at a different branch in the same target repo
+    pub fn retarget<'a>(&'a mut self, branch: &str) -> &'a mut PullRequest {
+        if let Some(ref mut r) = self.toRef {
+            r.id = format!("refs/heads/{}", branch);
+            r.displayId = None;
+        }
+        self
+    }
+
+    pub fn clear_reviewers<'a>(&'a mut self) -> &'a mut PullRequest {
+        self.reviewers.clear();
+        self
+    }
+
+    pub fn current_title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn current_description(&self) -> &str {
+        &self.description
+    }
+
+    /// Returns the review status (APPROVED, UNAPPROVED or NEEDS_WORK) of the
+    /// named reviewer
+    pub fn reviewer_status(&self, name: &str) -> Option<&str> {
+        self.reviewers
+            .iter()
+            .find(|r| r.user.name == name)
+            .and_then(|r| r.status.as_ref().map(|s| s.as_str()))
+    }
+
+    pub fn reviewer_names(&self) -> HashSet<String> {
+        self.reviewers
+            .iter()
+            .map(|r| r.user.name.clone())
+            .collect()
+    }
+
     pub fn project(&self) -> Option<String> {
         if let Some(ref r) = self.toRef {
             return Some(r.repository.project.key.clone());
@@ -208,6 +413,157 @@ impl PullRequest {
         }
         None
     }
+
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+
+    pub fn version(&self) -> Option<u64> {
+        self.version
+    }
+
+    pub fn source(&self) -> Option<&Reference> {
+        self.fromRef.as_ref()
+    }
+
+    /// Project keys and slugs are compared ignoring case, like the server
+    /// does, since the config or a remote url may spell them differently
+    pub fn is_from(&self, ref_id: &str, project: &str, slug: &str) -> bool {
+        match self.fromRef {
+            Some(ref r) => {
+                r.id == ref_id
+                    && r.repository.project.key.eq_ignore_ascii_case(project)
+                    && r.repository.slug.eq_ignore_ascii_case(slug)
+            }
+            None => false,
+        }
+    }
+
+    pub fn print_tty(&self, force_colorize: bool) {
+        let mut table = Table::new();
+        table.set_format(format::FormatBuilder::new().padding(1, 1).build());
+
+        let unknown = "unknown".to_string();
+        let id = self.id.map(|id| id.to_string()).unwrap_or(unknown.clone());
+        let version = self.version
+            .map(|v| v.to_string())
+            .unwrap_or(unknown.clone());
+        let from = self.fromRef
+            .as_ref()
+            .map(|r| r.display())
+            .unwrap_or(unknown.clone());
+        let to = self.toRef
+            .as_ref()
+            .map(|r| r.display())
+            .unwrap_or(unknown.clone());
+
+        table.add_row(Row::new(vec![Cell::new("id"), Cell::new(&id)]));
+        table.add_row(Row::new(vec![Cell::new("title"), Cell::new(&self.title)]));
+        table.add_row(Row::new(vec![
+            Cell::new("state"),
+            Cell::new(self.state.as_ref().unwrap_or(&unknown)),
+        ]));
+        table.add_row(Row::new(vec![Cell::new("from"), Cell::new(&from)]));
+        table.add_row(Row::new(vec![Cell::new("to"), Cell::new(&to)]));
+        table.add_row(Row::new(vec![
+            Cell::new("author"),
+            Cell::new(&self.author_name().unwrap_or("missing author".to_string())),
+        ]));
+        if let Some(created) = self.createdDate {
+            table.add_row(Row::new(vec![
+                Cell::new("created"),
+                Cell::new(&format_timestamp(created)),
+            ]));
+        }
+        if let Some(updated) = self.updatedDate {
+            table.add_row(Row::new(vec![
+                Cell::new("updated"),
+                Cell::new(&format_timestamp(updated)),
+            ]));
+        }
+        table.add_row(Row::new(vec![Cell::new("version"), Cell::new(&version)]));
+        for (rel, links) in &self.links {
+            for link in links {
+                table.add_row(Row::new(vec![
+                    Cell::new(&format!("link ({})", rel)),
+                    Cell::new(&link.href),
+                ]));
+            }
+        }
+        table.print_tty(force_colorize);
+
+        println!("");
+        if self.reviewers.is_empty() {
+            println!("No reviewers");
+        } else {
+            let mut reviewers = Table::new();
+            reviewers.set_format(output::titled_format());
+            reviewers.set_titles(Row::new(vec![
+                Cell::new("reviewer"),
+                Cell::new("status"),
+                Cell::new("approved"),
+            ]));
+            for reviewer in &self.reviewers {
+                let approved = match reviewer.approved {
+                    Some(true) => "yes",
+                    Some(false) => "no",
+                    None => "unknown",
+                };
+                reviewers.add_row(Row::new(vec![
+                    Cell::new(&reviewer.user.display_name()),
+                    Cell::new(reviewer.status.as_ref().unwrap_or(&unknown)),
+                    Cell::new(approved),
+                ]));
+            }
+            reviewers.print_tty(force_colorize);
+        }
+
+        println!("");
+        if self.description.is_empty() {
+            println!("No description");
+        } else {
+            println!("{}", self.description);
+        }
+    }
+
+    /// Shows what creating this pull request would send, i.e. the fields
+    /// that get serialized, without the JSON
+    pub fn print_preview(&self, force_colorize: bool) {
+        let mut table = Table::new();
+        table.set_format(format::FormatBuilder::new().padding(1, 1).build());
+
+        let missing = "missing".to_string();
+        let from = self.fromRef
+            .as_ref()
+            .map(|r| r.display())
+            .unwrap_or(missing.clone());
+        let to = self.toRef
+            .as_ref()
+            .map(|r| r.display())
+            .unwrap_or(missing.clone());
+        let reviewers = if self.reviewers.is_empty() {
+            "none".to_string()
+        } else {
+            self.reviewers
+                .iter()
+                .map(|r| r.user.name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ")
+        };
+
+        table.add_row(Row::new(vec![Cell::new("title"), Cell::new(&self.title)]));
+        table.add_row(Row::new(vec![Cell::new("from"), Cell::new(&from)]));
+        table.add_row(Row::new(vec![Cell::new("to"), Cell::new(&to)]));
+        table.add_row(Row::new(vec![Cell::new("reviewers"), Cell::new(&reviewers)]));
+        table.print_tty(force_colorize);
+
+        println!("");
+        if self.description.is_empty() {
+            println!("No description");
+        } else {
+            println!("{}", self.description);
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq)]
@@ -216,50 +572,797 @@ pub struct PullRequestList {
 }
 
 impl PullRequestList {
-    pub fn print_tty(&self, force_colorize: bool) {
-        let mut table = Table::new();
+    pub fn new(values: Vec<PullRequest>) -> PullRequestList {
+        PullRequestList { values: values }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn find_from(&self, ref_id: &str, project: &str, slug: &str) -> Option<&PullRequest> {
+        self.values.iter().find(|pr| pr.is_from(ref_id, project, slug))
+    }
+}
+
+impl Tabular for PullRequestList {
+    fn fields(&self) -> Vec<&'static str> {
+        vec![
+            "id",
+            "title",
+            "author",
+            "state",
+            "source",
+            "target",
+            "reviewers",
+            "approvals",
+            "created",
+            "updated",
+            "link",
+        ]
+    }
+
+    fn default_fields(&self) -> Vec<&'static str> {
+        vec!["id", "title", "author", "state", "link"]
+    }
+
+    fn records(&self) -> Vec<Vec<Value>> {
+        self.values
+            .iter()
+            .map(|pr| {
+                let mut reviewers: Vec<String> =
+                    pr.reviewers.iter().map(|r| r.user.display_name()).collect();
+                reviewers.sort();
+                let approvals = pr.reviewers
+                    .iter()
+                    .filter(|r| r.approved.unwrap_or(false))
+                    .count();
+                vec![
+                    output::optional(pr.id),
+                    Value::from(pr.title.clone()),
+                    output::optional(pr.author_name()),
+                    output::optional(pr.state.clone()),
+                    output::optional(pr.fromRef.as_ref().map(|r| r.branch())),
+                    output::optional(pr.toRef.as_ref().map(|r| r.branch())),
+                    Value::from(reviewers),
+                    Value::from(approvals as u64),
+                    output::optional(pr.createdDate.map(format_timestamp)),
+                    output::optional(pr.updatedDate.map(format_timestamp)),
+                    output::optional(pr.self_link()),
+                ]
+            })
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommentParent {
+    id: u64,
+}
+
+/// Places a comment on a line of a file in the pull request's diff
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Anchor {
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lineType: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fileType: Option<String>,
+}
 
-        let format = format::FormatBuilder::new()
-            .padding(1, 1)
-            .separator(
-                format::LinePosition::Title,
-                format::LineSeparator::new('-', '-', '-', '-'),
-            )
-            .build();
+impl Anchor {
+    /// `line_type` is one of ADDED, REMOVED or CONTEXT. Removed lines only
+    /// exist in the source version of the file, everything else is anchored
+    /// to the destination version.
+    pub fn new(path: &str, line: Option<u64>, line_type: &str) -> Anchor {
+        let line_type = line_type.to_uppercase();
+        let file_type = if line_type == "REMOVED" { "FROM" } else { "TO" };
+        Anchor {
+            path: path.to_string(),
+            line: line,
+            lineType: line.map(|_| line_type.clone()),
+            fileType: line.map(|_| file_type.to_string()),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self.line {
+            Some(line) => format!("{}:{}", self.path, line),
+            None => self.path.clone(),
+        }
+    }
+}
 
-        table.set_format(format);
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Comment {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    id: Option<u64>,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    parent: Option<CommentParent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anchor: Option<Anchor>,
+    #[serde(skip_serializing)]
+    author: Option<User>,
+    #[serde(skip_serializing)]
+    createdDate: Option<u64>,
+    #[serde(default, skip_serializing)]
+    comments: Vec<Comment>,
+}
+
+impl Comment {
+    pub fn new(text: &str) -> Comment {
+        Comment {
+            id: None,
+            text: text.to_string(),
+            parent: None,
+            anchor: None,
+            author: None,
+            createdDate: None,
+            comments: Vec::new(),
+        }
+    }
+
+    pub fn reply_to<'a>(&'a mut self, id: u64) -> &'a mut Comment {
+        self.parent = Some(CommentParent { id: id });
+        self
+    }
+
+    pub fn anchor<'a>(&'a mut self, anchor: Anchor) -> &'a mut Comment {
+        self.anchor = Some(anchor);
+        self
+    }
+
+    pub fn id(&self) -> Option<u64> {
+        self.id
+    }
+
+    /// Renders the comment and its replies, indenting replies under their
+    /// parent
+    fn render(&self, depth: usize, lines: &mut Vec<String>) {
+        let indent = "    ".repeat(depth);
+        let author = match self.author {
+            Some(ref user) => user.display_name(),
+            None => "unknown".to_string(),
+        };
+        let id = self.id.map(|id| format!(" (#{})", id)).unwrap_or(String::new());
+        lines.push(format!("{}{}{}:", indent, author, id));
+        for line in self.text.lines() {
+            lines.push(format!("{}  {}", indent, line));
+        }
+        for reply in &self.comments {
+            reply.render(depth + 1, lines);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DiffPath {
+    #[serde(rename = "toString")]
+    path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DiffLine {
+    #[serde(default)]
+    source: u64,
+    #[serde(default)]
+    destination: u64,
+    line: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Segment {
+    #[serde(rename = "type")]
+    segment_type: String,
+    lines: Vec<DiffLine>,
+}
+
+impl Segment {
+    fn prefix(&self) -> &'static str {
+        match self.segment_type.as_str() {
+            "ADDED" => "+",
+            "REMOVED" => "-",
+            _ => " ",
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Hunk {
+    sourceLine: u64,
+    sourceSpan: u64,
+    destinationLine: u64,
+    destinationSpan: u64,
+    #[serde(default)]
+    segments: Vec<Segment>,
+}
+
+/// A single file's diff, as returned in bitbucket server's structured format
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Diff {
+    source: Option<DiffPath>,
+    destination: Option<DiffPath>,
+    #[serde(default)]
+    hunks: Vec<Hunk>,
+    #[serde(default)]
+    binary: bool,
+}
+
+const RED: &'static str = "\x1b[31m";
+const GREEN: &'static str = "\x1b[32m";
+const CYAN: &'static str = "\x1b[36m";
+const BOLD: &'static str = "\x1b[1m";
+const RESET: &'static str = "\x1b[0m";
+
+/// The bounds of the +/- bars' width in `--stat` output, whatever the
+/// terminal's width
+const MIN_STAT_GRAPH_WIDTH: usize = 10;
+const MAX_STAT_GRAPH_WIDTH: usize = 40;
+
+fn paint(text: &str, color: &str, colorize: bool) -> String {
+    if colorize {
+        format!("{}{}{}", color, text, RESET)
+    } else {
+        text.to_string()
+    }
+}
+
+impl Diff {
+    /// The path of the file after the change, or before it for deletions
+    pub fn path(&self) -> String {
+        match (self.destination.as_ref(), self.source.as_ref()) {
+            (Some(dest), _) => dest.path.clone(),
+            (None, Some(src)) => src.path.clone(),
+            (None, None) => String::new(),
+        }
+    }
+
+    /// Counts of added and removed lines
+    pub fn stat(&self) -> (usize, usize) {
+        let mut added = 0;
+        let mut removed = 0;
+        for hunk in &self.hunks {
+            for segment in &hunk.segments {
+                match segment.segment_type.as_str() {
+                    "ADDED" => added += segment.lines.len(),
+                    "REMOVED" => removed += segment.lines.len(),
+                    _ => {}
+                }
+            }
+        }
+        (added, removed)
+    }
+
+    /// Renders the diff in unified format
+    pub fn unified(&self, colorize: bool) -> Vec<String> {
+        let mut lines = Vec::new();
+        let source = self.source
+            .as_ref()
+            .map(|p| format!("a/{}", p.path))
+            .unwrap_or("/dev/null".to_string());
+        let destination = self.destination
+            .as_ref()
+            .map(|p| format!("b/{}", p.path))
+            .unwrap_or("/dev/null".to_string());
+
+        lines.push(paint(
+            &format!("diff {} {}", source, destination),
+            BOLD,
+            colorize,
+        ));
+
+        if self.binary {
+            lines.push(format!("Binary files {} and {} differ", source, destination));
+            return lines;
+        }
+
+        lines.push(paint(&format!("--- {}", source), BOLD, colorize));
+        lines.push(paint(&format!("+++ {}", destination), BOLD, colorize));
+
+        for hunk in &self.hunks {
+            lines.push(paint(
+                &format!(
+                    "@@ -{},{} +{},{} @@",
+                    hunk.sourceLine, hunk.sourceSpan, hunk.destinationLine, hunk.destinationSpan
+                ),
+                CYAN,
+                colorize,
+            ));
+            for segment in &hunk.segments {
+                let color = match segment.segment_type.as_str() {
+                    "ADDED" => Some(GREEN),
+                    "REMOVED" => Some(RED),
+                    _ => None,
+                };
+                for line in &segment.lines {
+                    let text = format!("{}{}", segment.prefix(), line.line);
+                    match color {
+                        Some(color) => lines.push(paint(&text, color, colorize)),
+                        None => lines.push(text),
+                    }
+                }
+            }
+        }
+
+        lines
+    }
+
+    /// Returns up to `radius` lines either side of the anchored line, each
+    /// prefixed like a unified diff
+    pub fn context(&self, anchor: &Anchor, radius: usize) -> Vec<String> {
+        let target = match anchor.line {
+            Some(line) => line,
+            None => return Vec::new(),
+        };
+        let from_source = anchor.fileType.as_ref().map(|t| t == "FROM").unwrap_or(false);
+
+        let mut lines = Vec::new();
+        let mut position = None;
+        for hunk in &self.hunks {
+            for segment in &hunk.segments {
+                for line in &segment.lines {
+                    let number = if from_source {
+                        line.source
+                    } else {
+                        line.destination
+                    };
+                    let matches_type = match anchor.lineType {
+                        Some(ref t) => *t == segment.segment_type,
+                        None => true,
+                    };
+                    if position.is_none() && number == target && matches_type {
+                        position = Some(lines.len());
+                    }
+                    lines.push(format!("{}{}", segment.prefix(), line.line));
+                }
+            }
+        }
+
+        match position {
+            Some(pos) => {
+                let start = if pos > radius { pos - radius } else { 0 };
+                let end = ::std::cmp::min(pos + radius + 1, lines.len());
+                lines[start..end].to_vec()
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct DiffList {
+    #[serde(default)]
+    diffs: Vec<Diff>,
+}
+
+impl DiffList {
+    pub fn retain<F>(&mut self, f: F)
+    where
+        F: Fn(&str) -> bool,
+    {
+        self.diffs.retain(|d| f(&d.path()));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diffs.is_empty()
+    }
+
+    pub fn print(&self, colorize: bool) {
+        for diff in &self.diffs {
+            for line in diff.unified(colorize) {
+                println!("{}", line);
+            }
+        }
+    }
+
+    /// Prints a summary of changed lines per file, like `git diff --stat`.
+    /// The +/- bars are scaled down to fit the terminal, like git does.
+    pub fn print_stat(&self, colorize: bool) {
+        let width = self.diffs.iter().map(|d| d.path().len()).max().unwrap_or(0);
+        let stats: Vec<(usize, usize)> = self.diffs.iter().map(|d| d.stat()).collect();
+        let max_changes = stats.iter().map(|&(a, r)| a + r).max().unwrap_or(0);
+        let terminal_width = term_size::dimensions_stdout().map(|(w, _)| w).unwrap_or(80);
+        // " <path> | <count> " comes before the bar
+        let graph_width = terminal_width
+            .saturating_sub(width + max_changes.to_string().len() + 5)
+            .max(MIN_STAT_GRAPH_WIDTH)
+            .min(MAX_STAT_GRAPH_WIDTH);
+
+        let mut total_added = 0;
+        let mut total_removed = 0;
+
+        for (diff, &(added, removed)) in self.diffs.iter().zip(&stats) {
+            total_added += added;
+            total_removed += removed;
+            let changes = if diff.binary {
+                "Bin".to_string()
+            } else {
+                let (plus, minus) = if max_changes > graph_width {
+                    (
+                        scale_stat(added, graph_width, max_changes),
+                        scale_stat(removed, graph_width, max_changes),
+                    )
+                } else {
+                    (added, removed)
+                };
+                format!(
+                    "{} {}{}",
+                    added + removed,
+                    paint(&"+".repeat(plus), GREEN, colorize),
+                    paint(&"-".repeat(minus), RED, colorize)
+                )
+            };
+            println!(" {:width$} | {}", diff.path(), changes, width = width);
+        }
+
+        println!(
+            " {} file(s) changed, {} insertion(s)(+), {} deletion(s)(-)",
+            self.diffs.len(),
+            total_added,
+            total_removed
+        );
+    }
+}
+
+/// Scales a number of changed lines to a bar at most `width` long, the way
+/// `git diff --stat` does: anything changed gets at least one character
+fn scale_stat(lines: usize, width: usize, max_lines: usize) -> usize {
+    if lines == 0 || max_lines == 0 {
+        0
+    } else {
+        1 + lines * (width - 1) / max_lines
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Change {
+    path: DiffPath,
+    srcPath: Option<DiffPath>,
+    #[serde(rename = "type")]
+    change_type: String,
+}
+
+impl Change {
+    pub fn path(&self) -> &str {
+        &self.path.path
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ChangeList {
+    values: Vec<Change>,
+}
+
+impl ChangeList {
+    pub fn new(values: Vec<Change>) -> ChangeList {
+        ChangeList { values: values }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+}
+
+impl Tabular for ChangeList {
+    fn fields(&self) -> Vec<&'static str> {
+        vec!["type", "path", "src_path"]
+    }
+
+    fn records(&self) -> Vec<Vec<Value>> {
+        self.values
+            .iter()
+            .map(|change| {
+                vec![
+                    Value::from(change.change_type.to_lowercase()),
+                    Value::from(change.path.path.clone()),
+                    output::optional(change.srcPath.as_ref().map(|src| src.path.clone())),
+                ]
+            })
+            .collect()
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Commit {
+    displayId: String,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct CommitList {
+    #[serde(default)]
+    commits: Vec<Commit>,
+    #[serde(default)]
+    total: u64,
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Activity {
+    createdDate: u64,
+    user: User,
+    action: String,
+    commentAction: Option<String>,
+    comment: Option<Comment>,
+    commentAnchor: Option<Anchor>,
+    diff: Option<Diff>,
+    added: Option<CommitList>,
+    removed: Option<CommitList>,
+    #[serde(default)]
+    addedReviewers: Vec<User>,
+    #[serde(default)]
+    removedReviewers: Vec<User>,
+}
+
+impl Activity {
+    /// Describes the activity in (possibly) several lines of text
+    fn describe(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        match self.action.as_str() {
+            "OPENED" => lines.push("opened the pull request".to_string()),
+            "MERGED" => lines.push("merged the pull request".to_string()),
+            "DECLINED" => lines.push("declined the pull request".to_string()),
+            "REOPENED" => lines.push("reopened the pull request".to_string()),
+            "APPROVED" => lines.push("approved".to_string()),
+            "UNAPPROVED" => lines.push("removed their approval".to_string()),
+            "REVIEWED" => lines.push("marked the pull request as needing work".to_string()),
+            "RESCOPED" => {
+                if let Some(ref added) = self.added {
+                    if added.total > 0 {
+                        lines.push(format!("pushed {} commit(s)", added.total));
+                    }
+                    for commit in &added.commits {
+                        let summary = commit.message.lines().next().unwrap_or("");
+                        lines.push(format!("  + {} {}", commit.displayId, summary));
+                    }
+                }
+                if let Some(ref removed) = self.removed {
+                    if removed.total > 0 {
+                        lines.push(format!("removed {} commit(s)", removed.total));
+                    }
+                    for commit in &removed.commits {
+                        let summary = commit.message.lines().next().unwrap_or("");
+                        lines.push(format!("  - {} {}", commit.displayId, summary));
+                    }
+                }
+                if lines.is_empty() {
+                    lines.push("updated the source branch".to_string());
+                }
+            }
+            "UPDATED" => {
+                lines.push("updated the pull request".to_string());
+                for user in &self.addedReviewers {
+                    lines.push(format!("  + reviewer {}", user.display_name()));
+                }
+                for user in &self.removedReviewers {
+                    lines.push(format!("  - reviewer {}", user.display_name()));
+                }
+            }
+            "COMMENTED" => {
+                let verb = match self.commentAction.as_ref().map(|a| a.as_str()) {
+                    Some("REPLIED") => "replied",
+                    Some("EDITED") => "edited a comment",
+                    Some("DELETED") => "deleted a comment",
+                    _ => "commented",
+                };
+                match self.commentAnchor {
+                    Some(ref anchor) => lines.push(format!("{} on {}", verb, anchor.describe())),
+                    None => lines.push(verb.to_string()),
+                }
+
+                if let (Some(ref anchor), Some(ref diff)) = (self.commentAnchor.as_ref(), self.diff.as_ref()) {
+                    for line in diff.context(anchor, 2) {
+                        lines.push(format!("  | {}", line));
+                    }
+                }
+
+                if let Some(ref comment) = self.comment {
+                    comment.render(0, &mut lines);
+                }
+            }
+            other => lines.push(other.to_lowercase()),
+        }
+
+        lines
+    }
+}
+
+/// Pull request activity in chronological order
+pub struct Timeline {
+    activities: Vec<Activity>,
+}
+
+impl Timeline {
+    pub fn new(mut activities: Vec<Activity>) -> Timeline {
+        // the server returns the most recent activity first
+        activities.sort_by_key(|a| a.createdDate);
+        Timeline {
+            activities: activities,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.activities.is_empty()
+    }
+
+    pub fn print_tty(&self, force_colorize: bool) {
+        let mut table = Table::new();
+        table.set_format(output::titled_format());
         table.set_titles(Row::new(vec![
-            Cell::new("title"),
-            Cell::new("author"),
-            Cell::new("link"),
+            Cell::new("date"),
+            Cell::new("user"),
+            Cell::new("activity"),
         ]));
 
-        for pr in &self.values {
-            let mut display_name = pr.title.clone();
-            display_name.truncate(50);
-            let author = pr.author_name().unwrap_or("missing author".to_string());
-            let link = match pr.self_link() {
-                Some(l) => l.clone(),
-                None => "missing link".to_string(),
-            };
+        for activity in &self.activities {
             table.add_row(Row::new(vec![
-                Cell::new(&display_name),
-                Cell::new(&author),
-                Cell::new(&link),
+                Cell::new(&format_timestamp(activity.createdDate)),
+                Cell::new(&activity.user.display_name()),
+                Cell::new(&activity.describe().join("\n")),
             ]));
         }
 
         table.print_tty(force_colorize);
     }
+}
 
-    pub fn is_empty(&self) -> bool {
-        self.values.is_empty()
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct Veto {
+    summaryMessage: String,
+    #[serde(default)]
+    detailedMessage: String,
+}
+
+impl Veto {
+    pub fn message(&self) -> String {
+        if self.detailedMessage.is_empty() {
+            self.summaryMessage.clone()
+        } else {
+            format!("{}: {}", self.summaryMessage, self.detailedMessage)
+        }
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MergeStatus {
+    canMerge: bool,
+    #[serde(default)]
+    conflicted: bool,
+    outcome: Option<String>,
+    #[serde(default)]
+    vetoes: Vec<Veto>,
+}
+
+impl MergeStatus {
+    pub fn can_merge(&self) -> bool {
+        self.canMerge
+    }
+
+    pub fn is_conflicted(&self) -> bool {
+        self.conflicted
+    }
+
+    pub fn vetoes(&self) -> Vec<String> {
+        self.vetoes.iter().map(|v| v.message()).collect()
+    }
+
+    pub fn print_tty(&self, force_colorize: bool) {
+        let mut table = Table::new();
+        table.set_format(format::FormatBuilder::new().padding(1, 1).build());
+
+        let can_merge = if self.canMerge { "yes" } else { "no" };
+        let conflicted = if self.conflicted { "yes" } else { "no" };
+        table.add_row(Row::new(vec![Cell::new("can merge"), Cell::new(can_merge)]));
+        table.add_row(Row::new(vec![Cell::new("conflicted"), Cell::new(conflicted)]));
+        if let Some(ref outcome) = self.outcome {
+            table.add_row(Row::new(vec![Cell::new("outcome"), Cell::new(outcome)]));
+        }
+        for veto in &self.vetoes {
+            table.add_row(Row::new(vec![Cell::new("veto"), Cell::new(&veto.message())]));
+        }
+
+        table.print_tty(force_colorize);
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct MergeRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    strategyId: Option<String>,
+}
+
+impl MergeRequest {
+    pub fn new(message: Option<&str>, strategy: Option<&str>) -> MergeRequest {
+        MergeRequest {
+            message: message.map(|m| m.to_string()),
+            strategyId: strategy.map(|s| s.to_string()),
+        }
+    }
+}
+
+/// Body for the decline, reopen and delete endpoints, which all require the
+/// version of the pull request being modified
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct StateChange {
+    version: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment: Option<String>,
+}
+
+impl StateChange {
+    pub fn new(version: u64, comment: Option<&str>) -> StateChange {
+        StateChange {
+            version: version,
+            comment: comment.map(|c| c.to_string()),
+        }
+    }
+
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+}
+
+#[allow(non_snake_case)]
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ServerError {
+    message: Option<String>,
+    exceptionName: Option<String>,
+    #[serde(default)]
+    vetoes: Vec<Veto>,
+}
+
+/// The body bitbucket server sends along with most unsuccessful responses
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct ErrorResponse {
+    errors: Vec<ServerError>,
+}
+
+impl ErrorResponse {
+    pub fn messages(&self) -> Vec<String> {
+        self.errors
+            .iter()
+            .filter_map(|e| e.message.clone())
+            .collect()
+    }
+
+    /// Whether the request was for an older version of the pull request
+    pub fn is_out_of_date(&self) -> bool {
+        self.errors.iter().any(|e| match e.exceptionName {
+            Some(ref name) => name.ends_with(".PullRequestOutOfDateException"),
+            None => false,
+        })
+    }
+
+    pub fn vetoes(&self) -> Vec<String> {
+        let mut vetoes = Vec::new();
+        for error in &self.errors {
+            for veto in &error.vetoes {
+                vetoes.push(veto.message());
+            }
+        }
+        vetoes
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use serde_json;
 
     #[test]
     fn pull_request_construction() {
@@ -313,7 +1416,10 @@ mod tests {
                     name: name.to_string(),
                     displayName: None,
                     slug: None,
+                    active: None,
                 },
+                approved: None,
+                status: None,
             };
             reviewers.push(reviewer);
         }
@@ -326,4 +1432,385 @@ mod tests {
         pull_request.description("my description");
         assert_eq!("my description".to_string(), pull_request.description);
     }
+
+    #[test]
+    fn deserializing_pull_request() {
+        let data = r#"{
+            "id": 42,
+            "version": 3,
+            "title": "derp",
+            "description": "herp",
+            "state": "OPEN",
+            "createdDate": 1500000000000,
+            "updatedDate": 1500000100000,
+            "fromRef": {
+                "id": "refs/heads/feature",
+                "displayId": "feature",
+                "repository": {"slug": "repo", "project": {"key": "~foo"}}
+            },
+            "toRef": {
+                "id": "refs/heads/master",
+                "displayId": "master",
+                "repository": {"slug": "repo", "project": {"key": "PROJ"}}
+            },
+            "author": {"user": {"name": "foo", "displayName": "Foo"}},
+            "reviewers": [
+                {"user": {"name": "bar"}, "approved": true, "status": "APPROVED"}
+            ],
+            "links": {"self": [{"href": "https://example.com/pr/42"}]}
+        }"#;
+
+        let pull_request: PullRequest = serde_json::from_str(data).unwrap();
+        assert_eq!(Some(42), pull_request.id());
+        assert_eq!(Some(3), pull_request.version);
+        assert_eq!(Some("OPEN".to_string()), pull_request.state);
+        assert_eq!(Some(true), pull_request.reviewers[0].approved);
+        assert_eq!(
+            Some("APPROVED".to_string()),
+            pull_request.reviewers[0].status
+        );
+        assert_eq!(
+            Some("https://example.com/pr/42".to_string()),
+            pull_request.self_link()
+        );
+        assert!(pull_request.is_from("refs/heads/feature", "~foo", "repo"));
+        assert!(!pull_request.is_from("refs/heads/feature", "PROJ", "repo"));
+    }
+
+    #[test]
+    fn pull_request_source_ignores_case() {
+        let mut pull_request = PullRequest::new("derp");
+        pull_request.from_ref("feature", "repo", "~foo");
+
+        assert!(pull_request.is_from("refs/heads/feature", "~FOO", "Repo"));
+        assert!(!pull_request.is_from("refs/heads/Feature", "~foo", "repo"));
+    }
+
+    #[test]
+    fn finding_users_ignores_case() {
+        let found = UserSearchResult::new(vec![User::new("jsmith"), User::new("jsmithers")]);
+        assert_eq!(Some("jsmith"), found.find_by_name("JSmith").map(|u| u.name()));
+        assert_eq!(None, found.find_by_name("jsmit"));
+    }
+
+    #[test]
+    fn participant_construction() {
+        let participant = Participant::new("foo", "APPROVED");
+        assert!(participant.approved);
+        assert_eq!("APPROVED", participant.status());
+
+        let participant = Participant::new("foo", "NEEDS_WORK");
+        assert!(!participant.approved);
+        assert_eq!("NEEDS_WORK", participant.status());
+    }
+
+    #[test]
+    fn editing_pull_request_round_trips_server_fields() {
+        let data = r#"{
+            "id": 42,
+            "version": 3,
+            "title": "derp",
+            "state": "OPEN",
+            "fromRef": {
+                "id": "refs/heads/feature",
+                "displayId": "feature",
+                "repository": {"slug": "repo", "project": {"key": "~foo"}}
+            },
+            "toRef": {
+                "id": "refs/heads/master",
+                "displayId": "master",
+                "repository": {"slug": "repo", "project": {"key": "PROJ"}}
+            },
+            "reviewers": [{"user": {"name": "bar"}, "approved": true, "status": "APPROVED"}],
+            "links": {}
+        }"#;
+
+        let mut pull_request: PullRequest = serde_json::from_str(data).unwrap();
+        assert_eq!("", pull_request.current_description());
+
+        let mut expected_reviewers = HashSet::new();
+        expected_reviewers.insert("bar".to_string());
+        assert_eq!(expected_reviewers, pull_request.reviewer_names());
+
+        pull_request.title("herp").retarget("develop");
+
+        let body: serde_json::Value =
+            serde_json::from_str(&serde_json::to_string(&pull_request).unwrap()).unwrap();
+        assert_eq!(42, body["id"]);
+        assert_eq!(3, body["version"]);
+        assert_eq!("herp", body["title"]);
+        assert_eq!("", body["description"]);
+        assert_eq!("refs/heads/develop", body["toRef"]["id"]);
+        assert_eq!("PROJ", body["toRef"]["repository"]["project"]["key"]);
+        assert_eq!("bar", body["reviewers"][0]["user"]["name"]);
+    }
+
+    #[test]
+    fn serializing_comments() {
+        let body = serde_json::to_string(&Comment::new("derp")).unwrap();
+        assert_eq!(r#"{"text":"derp"}"#, body);
+
+        let mut reply = Comment::new("derp");
+        reply.reply_to(12);
+        let body = serde_json::to_string(&reply).unwrap();
+        assert_eq!(r#"{"text":"derp","parent":{"id":12}}"#, body);
+
+        let mut inline = Comment::new("derp");
+        inline.anchor(Anchor::new("src/main.rs", Some(10), "removed"));
+        let body = serde_json::to_string(&inline).unwrap();
+        assert_eq!(
+            r#"{"text":"derp","anchor":{"path":"src/main.rs","line":10,"lineType":"REMOVED","fileType":"FROM"}}"#,
+            body
+        );
+
+        let mut file = Comment::new("derp");
+        file.anchor(Anchor::new("src/main.rs", None, "added"));
+        let body = serde_json::to_string(&file).unwrap();
+        assert_eq!(r#"{"text":"derp","anchor":{"path":"src/main.rs"}}"#, body);
+    }
+
+    #[test]
+    fn inline_comment_activity() {
+        let data = r#"{
+            "id": 1,
+            "createdDate": 1500000000000,
+            "user": {"name": "foo", "displayName": "Foo"},
+            "action": "COMMENTED",
+            "commentAction": "ADDED",
+            "comment": {
+                "id": 10,
+                "text": "why?",
+                "author": {"name": "foo", "displayName": "Foo"},
+                "comments": [
+                    {"id": 11, "text": "because", "author": {"name": "bar", "displayName": "Bar"}}
+                ]
+            },
+            "commentAnchor": {
+                "path": "src/main.rs",
+                "line": 3,
+                "lineType": "ADDED",
+                "fileType": "TO"
+            },
+            "diff": {
+                "source": {"toString": "src/main.rs"},
+                "destination": {"toString": "src/main.rs"},
+                "hunks": [{
+                    "sourceLine": 1,
+                    "sourceSpan": 4,
+                    "destinationLine": 1,
+                    "destinationSpan": 5,
+                    "segments": [
+                        {"type": "CONTEXT", "lines": [
+                            {"source": 1, "destination": 1, "line": "one"},
+                            {"source": 2, "destination": 2, "line": "two"}
+                        ]},
+                        {"type": "ADDED", "lines": [
+                            {"source": 2, "destination": 3, "line": "three"}
+                        ]},
+                        {"type": "CONTEXT", "lines": [
+                            {"source": 3, "destination": 4, "line": "four"},
+                            {"source": 4, "destination": 5, "line": "five"},
+                            {"source": 5, "destination": 6, "line": "six"}
+                        ]}
+                    ]
+                }]
+            }
+        }"#;
+
+        let activity: Activity = serde_json::from_str(data).unwrap();
+        assert_eq!(
+            vec![
+                "commented on src/main.rs:3".to_string(),
+                "  |  one".to_string(),
+                "  |  two".to_string(),
+                "  | +three".to_string(),
+                "  |  four".to_string(),
+                "  |  five".to_string(),
+                "Foo (#10):".to_string(),
+                "  why?".to_string(),
+                "    Bar (#11):".to_string(),
+                "      because".to_string(),
+            ],
+            activity.describe()
+        );
+    }
+
+    #[test]
+    fn rendering_unified_diff() {
+        let data = r#"{
+            "source": {"toString": "src/main.rs"},
+            "destination": {"toString": "src/main.rs"},
+            "hunks": [{
+                "sourceLine": 1,
+                "sourceSpan": 2,
+                "destinationLine": 1,
+                "destinationSpan": 2,
+                "segments": [
+                    {"type": "CONTEXT", "lines": [{"source": 1, "destination": 1, "line": "one"}]},
+                    {"type": "REMOVED", "lines": [{"source": 2, "destination": 2, "line": "two"}]},
+                    {"type": "ADDED", "lines": [{"source": 3, "destination": 2, "line": "2"}]}
+                ]
+            }]
+        }"#;
+
+        let diff: Diff = serde_json::from_str(data).unwrap();
+        assert_eq!("src/main.rs", diff.path());
+        assert_eq!((1, 1), diff.stat());
+        assert_eq!(
+            vec![
+                "diff a/src/main.rs b/src/main.rs".to_string(),
+                "--- a/src/main.rs".to_string(),
+                "+++ b/src/main.rs".to_string(),
+                "@@ -1,2 +1,2 @@".to_string(),
+                " one".to_string(),
+                "-two".to_string(),
+                "+2".to_string(),
+            ],
+            diff.unified(false)
+        );
+    }
+
+    #[test]
+    fn rendering_new_file_diff() {
+        let data = r#"{
+            "source": null,
+            "destination": {"toString": "README.md"},
+            "hunks": []
+        }"#;
+
+        let diff: Diff = serde_json::from_str(data).unwrap();
+        assert_eq!("README.md", diff.path());
+        assert_eq!("--- /dev/null", diff.unified(false)[1]);
+    }
+
+    #[test]
+    fn scaling_stat_bars() {
+        assert_eq!(0, scale_stat(0, 40, 1000));
+        assert_eq!(1, scale_stat(1, 40, 1000));
+        assert_eq!(20, scale_stat(500, 40, 1000));
+        assert_eq!(40, scale_stat(1000, 40, 1000));
+    }
+
+    #[test]
+    fn timeline_is_chronological() {
+        let data = r#"[
+            {"createdDate": 3, "user": {"name": "foo"}, "action": "MERGED"},
+            {"createdDate": 1, "user": {"name": "foo"}, "action": "OPENED"},
+            {"createdDate": 2, "user": {"name": "bar"}, "action": "APPROVED"}
+        ]"#;
+
+        let activities: Vec<Activity> = serde_json::from_str(data).unwrap();
+        let timeline = Timeline::new(activities);
+        let actions: Vec<&str> = timeline
+            .activities
+            .iter()
+            .map(|a| a.action.as_str())
+            .collect();
+        assert_eq!(vec!["OPENED", "APPROVED", "MERGED"], actions);
+    }
+
+    #[test]
+    fn repository_clone_urls() {
+        let data = r#"{
+            "slug": "repo",
+            "project": {"key": "~foo"},
+            "links": {
+                "clone": [
+                    {"href": "ssh://git@example.com:7999/~foo/repo.git", "name": "ssh"},
+                    {"href": "https://example.com/scm/~foo/repo.git", "name": "http"}
+                ]
+            }
+        }"#;
+
+        let repository: Repository = serde_json::from_str(data).unwrap();
+        assert_eq!(
+            Some("ssh://git@example.com:7999/~foo/repo.git".to_string()),
+            repository.clone_url(true)
+        );
+        assert_eq!(
+            Some("https://example.com/scm/~foo/repo.git".to_string()),
+            repository.clone_url(false)
+        );
+    }
+
+    #[test]
+    fn deserializing_default_branch() {
+        let data = r#"{
+            "id": "refs/heads/develop",
+            "displayId": "develop",
+            "type": "BRANCH",
+            "latestCommit": "8d51122def5632836d1cb1026e879069e10a1e13",
+            "isDefault": true
+        }"#;
+
+        let branch: Branch = serde_json::from_str(data).unwrap();
+        assert_eq!("develop", branch.name());
+        assert_eq!(Some("8d51122def5632836d1cb1026e879069e10a1e13"), branch.head());
+        assert!(branch.is_default());
+    }
+
+    #[test]
+    fn deserializing_pages() {
+        let data = r#"{
+            "size": 1,
+            "limit": 1,
+            "start": 0,
+            "isLastPage": false,
+            "nextPageStart": 1,
+            "values": [
+                {"name": "foo", "displayName": "Foo", "slug": "foo"},
+                {"name": "bar", "displayName": "Bar", "slug": "bar", "active": false}
+            ]
+        }"#;
+
+        let page: Page<User> = serde_json::from_str(data).unwrap();
+        assert_eq!("foo", page.values[0].name);
+        assert!(page.values[0].is_active());
+        assert!(!page.values[1].is_active());
+        assert!(!page.isLastPage);
+        assert_eq!(Some(1), page.nextPageStart);
+
+        let page: Page<User> = serde_json::from_str(r#"{"values": []}"#).unwrap();
+        assert!(page.values.is_empty());
+        assert!(!page.isLastPage);
+        assert_eq!(None, page.nextPageStart);
+    }
+
+    #[test]
+    fn deserializing_merge_vetoes() {
+        let data = r#"{
+            "errors": [{
+                "context": null,
+                "message": "Merging the pull request has been vetoed.",
+                "exceptionName": "com.atlassian.bitbucket.pull.PullRequestMergeVetoedException",
+                "conflicted": false,
+                "vetoes": [
+                    {"summaryMessage": "Not enough approvals", "detailedMessage": "Requires 2 approvals"},
+                    {"summaryMessage": "Open tasks"}
+                ]
+            }]
+        }"#;
+
+        let response: ErrorResponse = serde_json::from_str(data).unwrap();
+        assert_eq!(
+            vec!["Merging the pull request has been vetoed.".to_string()],
+            response.messages()
+        );
+        assert_eq!(
+            vec![
+                "Not enough approvals: Requires 2 approvals".to_string(),
+                "Open tasks".to_string(),
+            ],
+            response.vetoes()
+        );
+    }
+
+    #[test]
+    fn serializing_new_pull_request_omits_server_fields() {
+        let pull_request = PullRequest::new("derp");
+        let body = serde_json::to_string(&pull_request).unwrap();
+        assert!(!body.contains("\"id\""));
+        assert!(!body.contains("\"version\""));
+        assert!(!body.contains("\"state\""));
+    }
 }
@@ -1,26 +1,174 @@
+use std::collections::VecDeque;
 use std::io::Read;
 
 use hyper::Client;
 use hyper::Url;
 use hyper::header::{Authorization, ContentType, Headers};
+use hyper::method::Method;
+use hyper::status::StatusCode;
 use hyper::mime::{Attr, Mime, SubLevel, TopLevel, Value};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
 use serde_json;
 
-use bitbucket_data::{PullRequest, PullRequestList, UserSearchResult};
+use bitbucket_data::{Activity, Branch, Change, ChangeList, Comment, DiffList, ErrorResponse, MergeRequest, MergeStatus, Page, Participant,
+                     PullRequest, PullRequestList, StateChange, User, UserSearchResult};
 use config::Project;
+use credentials::Auth;
 use error::{Error, ErrorKind, Result};
+use util;
+
+/// Identifies a single pull request on the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullRequestRef {
+    pub project: String,
+    pub slug: String,
+    pub id: u64,
+}
+
+impl PullRequestRef {
+    /// Parses a pull request url as printed by `bb list`, such as
+    /// https://host/projects/KEY/repos/slug/pull-requests/12/overview or
+    /// https://host/users/name/repos/slug/pull-requests/12
+    pub fn from_url(raw: &str) -> Result<PullRequestRef> {
+        let invalid = || Error::from(ErrorKind::InvalidPullRequestId(raw.to_string()));
+        let url = Url::parse(raw).map_err(|_| invalid())?;
+        let segments: Vec<&str> = url.path_segments().ok_or_else(&invalid)?.collect();
+
+        let start = segments
+            .iter()
+            .position(|s| *s == "projects" || *s == "users")
+            .ok_or_else(&invalid)?;
+
+        if segments.len() < start + 6 || segments[start + 2] != "repos"
+            || segments[start + 4] != "pull-requests"
+        {
+            return Err(invalid());
+        }
+
+        let project = if segments[start] == "users" {
+            format!("~{}", segments[start + 1])
+        } else {
+            segments[start + 1].to_string()
+        };
+
+        let id = segments[start + 5].parse::<u64>().map_err(|_| invalid())?;
+
+        Ok(PullRequestRef {
+            project: project,
+            slug: segments[start + 3].to_string(),
+            id: id,
+        })
+    }
+
+    fn component(&self) -> String {
+        format!(
+            "rest/api/1.0/projects/{}/repos/{}/pull-requests/{}",
+            self.project, self.slug, self.id
+        )
+    }
+}
+
+/// Narrows down pull request listings. `role` is one of AUTHOR, REVIEWER or
+/// PARTICIPANT, `state` one of OPEN, MERGED, DECLINED or ALL and
+/// `participant_status` one of APPROVED, UNAPPROVED or NEEDS_WORK.
+/// `source` is a full ref (e.g. refs/heads/feature) that pull requests must
+/// be from, which only repo listings support.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullRequestFilter {
+    pub role: Option<String>,
+    pub state: String,
+    pub participant_status: Option<String>,
+    pub order: Option<String>,
+    pub source: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl Default for PullRequestFilter {
+    fn default() -> PullRequestFilter {
+        PullRequestFilter {
+            role: None,
+            state: "OPEN".to_string(),
+            participant_status: None,
+            order: None,
+            source: None,
+            limit: None,
+        }
+    }
+}
+
+/// The number of items requested per page when following paged responses
+const PAGE_SIZE: usize = 100;
+
+/// Iterates over every item of a paged list endpoint, requesting the next page
+/// only once the current one has been consumed
+pub struct Paged<'a, T> {
+    client: &'a Bitbucket,
+    url: Url,
+    next_start: Option<u64>,
+    buffer: VecDeque<T>,
+    debug: bool,
+}
+
+impl<'a, T: DeserializeOwned> Iterator for Paged<'a, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        if self.buffer.is_empty() {
+            let start = match self.next_start {
+                Some(start) => start,
+                None => return None,
+            };
+
+            let mut url = self.url.clone();
+            url.query_pairs_mut()
+                .append_pair("start", &start.to_string())
+                .append_pair("limit", &PAGE_SIZE.to_string());
+
+            let page: Page<T> = match self.client.get(url, self.debug) {
+                Ok(page) => page,
+                Err(e) => {
+                    self.next_start = None;
+                    return Some(Err(e));
+                }
+            };
+
+            self.next_start = if page.isLastPage {
+                None
+            } else {
+                page.nextPageStart
+            };
+            self.buffer.extend(page.values);
+        }
+
+        self.buffer.pop_front().map(Ok)
+    }
+}
+
+/// Collects at most `limit` items, stopping at the first error
+fn collect<T, I>(items: I, limit: Option<usize>) -> Result<Vec<T>>
+where
+    I: Iterator<Item = Result<T>>,
+{
+    match limit {
+        Some(limit) => items.take(limit).collect(),
+        None => items.collect(),
+    }
+}
 
 pub struct Bitbucket {
     client: Client,
     headers: Headers,
     base_url: Url,
+    /// Print requests that would change anything instead of sending them
+    dry_run: bool,
 }
 
 impl Bitbucket {
-    pub fn new(auth: String, base_url: String) -> Result<Bitbucket> {
+    pub fn new(auth: &Auth, base_url: String, dry_run: bool) -> Result<Bitbucket> {
         let url = Url::parse(base_url.as_str())?;
         let mut headers = Headers::new();
-        headers.set(Authorization(format!("Basic {}", auth).to_owned()));
+        headers.set(Authorization(auth.header()));
         headers.set(ContentType(Mime(
             TopLevel::Application,
             SubLevel::Json,
@@ -30,9 +178,69 @@ impl Bitbucket {
             client: Client::new(),
             headers: headers,
             base_url: url,
+            dry_run: dry_run,
         })
     }
 
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    /// The name of the repo's default branch
+    pub fn default_branch(&self, project: &str, slug: &str, debug: bool) -> Result<String> {
+        let component = format!(
+            "rest/api/1.0/projects/{}/repos/{}/branches/default",
+            project, slug
+        );
+        let url = self.base_url.join(&component)?;
+        let branch: Branch = self.get(url, debug)?;
+        Ok(branch.name().to_string())
+    }
+
+    /// A repo's branches, most recently modified first
+    pub fn branches(
+        &self,
+        project: &str,
+        slug: &str,
+        limit: Option<usize>,
+        debug: bool,
+    ) -> Result<Vec<Branch>> {
+        let component = format!(
+            "rest/api/1.0/projects/{}/repos/{}/branches",
+            project, slug
+        );
+        let mut url = self.base_url.join(&component)?;
+        url.query_pairs_mut().append_pair("orderBy", "MODIFICATION");
+
+        collect(self.paged::<Branch>(url, debug), limit)
+    }
+
+    /// The commit a branch of the repo points at, or nothing if the repo has
+    /// no such branch
+    pub fn branch_head(
+        &self,
+        project: &str,
+        slug: &str,
+        branch: &str,
+        debug: bool,
+    ) -> Result<Option<String>> {
+        let component = format!(
+            "rest/api/1.0/projects/{}/repos/{}/branches",
+            project, slug
+        );
+        let mut url = self.base_url.join(&component)?;
+        url.query_pairs_mut().append_pair("filterText", branch);
+
+        // the filter matches anywhere in the name, so look for the exact one
+        for found in self.paged::<Branch>(url, debug) {
+            let found = found?;
+            if found.name() == branch {
+                return Ok(found.head().map(|head| head.to_string()));
+            }
+        }
+        Ok(None)
+    }
+
     pub fn branch_exists(&self, project: &Project, branch: &str, debug: bool) -> Result<bool> {
         /*
          * So bitbucket server is really annoying in that they don't let you
@@ -62,9 +270,8 @@ impl Bitbucket {
     pub fn create_pull_request(
         &self,
         pull_request: &PullRequest,
-        dry: bool,
         debug: bool,
-    ) -> Result<Url> {
+    ) -> Result<Option<PullRequest>> {
         let component = format!(
             "rest/api/1.0/projects/{}/repos/{}/pull-requests",
             pull_request
@@ -75,74 +282,351 @@ impl Bitbucket {
                 .ok_or::<Error>(ErrorKind::InvalidPullRequest("Missing toRef".to_string()).into())?
         );
         let url = self.base_url.join(&component)?;
-        let body = serde_json::to_string(pull_request)?;
+        self.post(url, pull_request, debug)
+    }
 
-        if debug {
-            println!("{}", body);
+    pub fn list_pull_requests(
+        &self,
+        filter: &PullRequestFilter,
+        debug: bool,
+    ) -> Result<PullRequestList> {
+        let mut url = self.base_url.join("rest/api/1.0/dashboard/pull-requests")?;
+
+        // annoyingly, the only way to specify "all" for these is to not
+        // include the parameter at all
+        if filter.state != "ALL" {
+            url.query_pairs_mut().append_pair("state", &filter.state);
         }
 
-        if dry {
-            println!("Dry run: \"{}\"", body);
-            return Err(ErrorKind::DryRun.into());
+        if let Some(ref role) = filter.role {
+            url.query_pairs_mut().append_pair("role", role);
         }
 
-        let mut res = self.client
-            .post(url)
-            .headers(self.headers.clone())
-            .body(body.as_str())
-            .send()?;
-        let mut response_body = String::new();
-        res.read_to_string(&mut response_body)?;
+        if let Some(ref status) = filter.participant_status {
+            url.query_pairs_mut()
+                .append_pair("participantStatus", status);
+        }
 
-        if res.status.is_success() {
-            if debug {
-                println!("{}", response_body);
+        if let Some(ref order) = filter.order {
+            url.query_pairs_mut().append_pair("order", order);
+        }
+
+        let values = collect(self.paged::<PullRequest>(url, debug), filter.limit)?;
+        Ok(PullRequestList::new(values))
+    }
+
+    pub fn user(&self, filter: &str, limit: Option<usize>, debug: bool) -> Result<UserSearchResult> {
+        let mut url = self.base_url.join("rest/api/1.0/users")?;
+        url.query_pairs_mut().append_pair("filter", filter);
+
+        let values = collect(self.paged::<User>(url, debug), limit)?;
+        Ok(UserSearchResult::new(values))
+    }
+
+    pub fn get_pull_request(&self, pr: &PullRequestRef, debug: bool) -> Result<PullRequest> {
+        let url = self.base_url.join(&pr.component())?;
+        self.get(url, debug)
+    }
+
+    pub fn update_pull_request(
+        &self,
+        pr: &PullRequestRef,
+        pull_request: &PullRequest,
+        debug: bool,
+    ) -> Result<Option<PullRequest>> {
+        let url = self.base_url.join(&pr.component())?;
+        self.put(url, pull_request, debug)
+    }
+
+    pub fn add_comment(
+        &self,
+        pr: &PullRequestRef,
+        comment: &Comment,
+        debug: bool,
+    ) -> Result<Option<Comment>> {
+        let component = format!("{}/comments", pr.component());
+        let url = self.base_url.join(&component)?;
+        self.post(url, comment, debug)
+    }
+
+    pub fn activities(
+        &self,
+        pr: &PullRequestRef,
+        limit: Option<usize>,
+        debug: bool,
+    ) -> Result<Vec<Activity>> {
+        let component = format!("{}/activities", pr.component());
+        let url = self.base_url.join(&component)?;
+        collect(self.paged(url, debug), limit)
+    }
+
+    pub fn diff(
+        &self,
+        pr: &PullRequestRef,
+        context_lines: Option<u32>,
+        debug: bool,
+    ) -> Result<DiffList> {
+        let component = format!("{}/diff", pr.component());
+        let mut url = self.base_url.join(&component)?;
+        if let Some(context_lines) = context_lines {
+            url.query_pairs_mut()
+                .append_pair("contextLines", &context_lines.to_string());
+        }
+        self.get(url, debug)
+    }
+
+    /// The files changed by a pull request, only those matching one of the
+    /// glob patterns if any are given
+    pub fn changes(
+        &self,
+        pr: &PullRequestRef,
+        patterns: &[&str],
+        limit: Option<usize>,
+        debug: bool,
+    ) -> Result<ChangeList> {
+        let component = format!("{}/changes", pr.component());
+        let url = self.base_url.join(&component)?;
+
+        // filter before the limit is applied, so it counts matching files
+        let changes = self.paged::<Change>(url, debug).filter(|change| match *change {
+            Ok(ref change) => {
+                patterns.is_empty() || patterns.iter().any(|p| util::glob_match(p, change.path()))
             }
-            let data = serde_json::from_str(response_body.as_str())?;
-            get_self_url(&data)
+            Err(_) => true,
+        });
+
+        let values = collect(changes, limit)?;
+        Ok(ChangeList::new(values))
+    }
+
+    pub fn repo_pull_requests(
+        &self,
+        project: &str,
+        slug: &str,
+        filter: &PullRequestFilter,
+        debug: bool,
+    ) -> Result<PullRequestList> {
+        let component = format!("rest/api/1.0/projects/{}/repos/{}/pull-requests", project, slug);
+        let mut url = self.base_url.join(&component)?;
+        url.query_pairs_mut().append_pair("state", &filter.state);
+
+        if let Some(ref order) = filter.order {
+            url.query_pairs_mut().append_pair("order", order);
+        }
+
+        if let Some(ref source) = filter.source {
+            url.query_pairs_mut()
+                .append_pair("at", source)
+                .append_pair("direction", "OUTGOING");
+        }
+
+        // unlike the dashboard, the repo endpoint needs to be told who "you"
+        // are, and can't filter by participant status at all
+        let name = if filter.role.is_some() || filter.participant_status.is_some() {
+            Some(self.current_user_name(debug)?)
         } else {
-            Err(ErrorKind::RequestError(response_body).into())
+            None
+        };
+
+        if let (Some(ref role), Some(ref name)) = (filter.role.as_ref(), name.as_ref()) {
+            url.query_pairs_mut()
+                .append_pair("role.1", role)
+                .append_pair("username.1", name);
         }
+
+        let pull_requests = self.paged::<PullRequest>(url, debug).filter(|pr| {
+            match (filter.participant_status.as_ref(), name.as_ref()) {
+                (Some(status), Some(name)) => match *pr {
+                    Ok(ref pr) => pr.reviewer_status(name) == Some(status.as_str()),
+                    Err(_) => true,
+                },
+                _ => true,
+            }
+        });
+
+        let values = collect(pull_requests, filter.limit)?;
+        Ok(PullRequestList::new(values))
     }
 
-    pub fn list_pull_requests(&self, debug: bool, role: &str) -> Result<PullRequestList> {
-        let mut url = self.base_url.join("rest/api/1.0/dashboard/pull-requests")?;
-        url.query_pairs_mut().append_pair("state", "OPEN");
+    pub fn approve(&self, pr: &PullRequestRef, debug: bool) -> Result<Option<Participant>> {
+        self.set_participant_status(pr, "APPROVED", debug)
+    }
 
-        // annoyingly, the only way to specify this behavior is to not include
-        // the parameter at all
-        if role != "ALL" {
-            url.query_pairs_mut().append_pair("role", role);
+    pub fn unapprove(&self, pr: &PullRequestRef, debug: bool) -> Result<Option<Participant>> {
+        self.set_participant_status(pr, "UNAPPROVED", debug)
+    }
+
+    pub fn needs_work(&self, pr: &PullRequestRef, debug: bool) -> Result<Option<Participant>> {
+        self.set_participant_status(pr, "NEEDS_WORK", debug)
+    }
+
+    pub fn merge_status(&self, pr: &PullRequestRef, debug: bool) -> Result<MergeStatus> {
+        let component = format!("{}/merge", pr.component());
+        let url = self.base_url.join(&component)?;
+        self.get(url, debug)
+    }
+
+    pub fn merge(
+        &self,
+        pr: &PullRequestRef,
+        version: u64,
+        request: &MergeRequest,
+        debug: bool,
+    ) -> Result<Option<PullRequest>> {
+        let component = format!("{}/merge", pr.component());
+        let mut url = self.base_url.join(&component)?;
+        url.query_pairs_mut()
+            .append_pair("version", &version.to_string());
+        self.post(url, request, debug)
+    }
+
+    pub fn decline(
+        &self,
+        pr: &PullRequestRef,
+        version: u64,
+        comment: Option<&str>,
+        debug: bool,
+    ) -> Result<Option<PullRequest>> {
+        self.change_state(pr, "decline", &StateChange::new(version, comment), debug)
+    }
+
+    pub fn reopen(&self, pr: &PullRequestRef, version: u64, debug: bool) -> Result<Option<PullRequest>> {
+        self.change_state(pr, "reopen", &StateChange::new(version, None), debug)
+    }
+
+    /// Returns whether the pull request was actually deleted
+    pub fn delete_pull_request(&self, pr: &PullRequestRef, version: u64, debug: bool) -> Result<bool> {
+        let url = self.base_url.join(&pr.component())?;
+        let body = serde_json::to_string(&StateChange::new(version, None))?;
+        let response = self.execute(Method::Delete, url, Some(body), debug)?;
+        Ok(response.is_some())
+    }
+
+    fn change_state(
+        &self,
+        pr: &PullRequestRef,
+        action: &str,
+        change: &StateChange,
+        debug: bool,
+    ) -> Result<Option<PullRequest>> {
+        let component = format!("{}/{}", pr.component(), action);
+        let mut url = self.base_url.join(&component)?;
+        url.query_pairs_mut()
+            .append_pair("version", &change.version().to_string());
+        self.post(url, change, debug)
+    }
+
+    /// Returns the name of the user the client is authenticated as
+    pub fn current_user_name(&self, debug: bool) -> Result<String> {
+        let url = self.base_url.join("plugins/servlet/applinks/whoami")?;
+        Ok(self.get_raw(url, debug)?.trim().to_string())
+    }
+
+    /// Returns the slug of the user the client is authenticated as
+    pub fn current_user_slug(&self, debug: bool) -> Result<String> {
+        let name = self.current_user_name(debug)?;
+
+        // the slug is usually the name, but not always, so look it up
+        let users = self.user(&name, None, debug)?;
+        match users.find_by_name(&name).and_then(|user| user.slug()) {
+            Some(slug) => Ok(slug.to_string()),
+            None => Ok(name),
         }
+    }
 
-        if debug {
-            println!("{}", url);
+    fn set_participant_status(
+        &self,
+        pr: &PullRequestRef,
+        status: &str,
+        debug: bool,
+    ) -> Result<Option<Participant>> {
+        let slug = self.current_user_slug(debug)?;
+        let component = format!("{}/participants/{}", pr.component(), slug);
+        let url = self.base_url.join(&component)?;
+        self.put(url, &Participant::new(&slug, status), debug)
+    }
+
+    fn paged<T: DeserializeOwned>(&self, url: Url, debug: bool) -> Paged<T> {
+        Paged {
+            client: self,
+            url: url,
+            next_start: Some(0),
+            buffer: VecDeque::new(),
+            debug: debug,
         }
+    }
 
-        let mut res = self.client.get(url).headers(self.headers.clone()).send()?;
+    fn get<T: DeserializeOwned>(&self, url: Url, debug: bool) -> Result<T> {
+        let response_body = self.get_raw(url, debug)?;
+        let res = serde_json::from_str(response_body.as_str())?;
+        Ok(res)
+    }
 
-        let mut response_body = String::new();
-        res.read_to_string(&mut response_body)?;
-        if res.status.is_success() {
-            if debug {
-                println!("{}", response_body);
-            }
-            let res = serde_json::from_str(response_body.as_str())?;
-            Ok(res)
-        } else {
-            Err(ErrorKind::RequestError(response_body).into())
+    fn get_raw(&self, url: Url, debug: bool) -> Result<String> {
+        // reads are always sent, even on a dry run
+        let response_body = self.execute(Method::Get, url, None, debug)?;
+        Ok(response_body.unwrap_or_default())
+    }
+
+    /// Nothing on a dry run
+    fn put<B: Serialize, T: DeserializeOwned>(
+        &self,
+        url: Url,
+        body: &B,
+        debug: bool,
+    ) -> Result<Option<T>> {
+        let body = serde_json::to_string(body)?;
+        match self.execute(Method::Put, url, Some(body), debug)? {
+            Some(response_body) => Ok(Some(serde_json::from_str(response_body.as_str())?)),
+            None => Ok(None),
         }
     }
 
-    pub fn user(&self, filter: &str, debug: bool) -> Result<UserSearchResult> {
-        let mut url = self.base_url.join("rest/api/1.0/users")?;
-        url.query_pairs_mut().append_pair("filter", filter);
+    /// Nothing on a dry run
+    fn post<B: Serialize, T: DeserializeOwned>(
+        &self,
+        url: Url,
+        body: &B,
+        debug: bool,
+    ) -> Result<Option<T>> {
+        let body = serde_json::to_string(body)?;
+        match self.execute(Method::Post, url, Some(body), debug)? {
+            Some(response_body) => Ok(Some(serde_json::from_str(response_body.as_str())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Sends the request and returns the response body, unless it's a dry run
+    /// and the request would change something, in which case the request is
+    /// only printed
+    fn execute(
+        &self,
+        method: Method,
+        url: Url,
+        body: Option<String>,
+        debug: bool,
+    ) -> Result<Option<String>> {
+        // on stderr, so it doesn't end up in output meant for other programs
+        if self.dry_run && method != Method::Get {
+            eprintln!("Dry run: {} {}", method, url);
+            if let Some(ref body) = body {
+                eprintln!("{}", pretty_json(body)?);
+            }
+            return Ok(None);
+        }
 
         if debug {
-            println!("{}", url);
+            println!("{} {}", method, url);
+            if let Some(ref body) = body {
+                println!("{}", body);
+            }
         }
 
-        let mut res = self.client.get(url).headers(self.headers.clone()).send()?;
+        let mut req = self.client.request(method, url).headers(self.headers.clone());
+        if let Some(ref body) = body {
+            req = req.body(body.as_str());
+        }
+        let mut res = req.send()?;
 
         let mut response_body = String::new();
         res.read_to_string(&mut response_body)?;
@@ -150,15 +634,45 @@ impl Bitbucket {
             if debug {
                 println!("{}", response_body);
             }
-            let res = serde_json::from_str(response_body.as_str())?;
-            Ok(res)
+            Ok(Some(response_body))
         } else {
-            Err(ErrorKind::RequestError(response_body).into())
+            Err(response_error(res.status, response_body))
         }
     }
 }
 
-fn get_self_url(pull_request: &PullRequest) -> Result<Url> {
+fn pretty_json(body: &str) -> Result<String> {
+    let value: serde_json::Value = serde_json::from_str(body)?;
+    Ok(serde_json::to_string_pretty(&value)?)
+}
+
+/// Turns an unsuccessful response into the most specific error we can,
+/// falling back to the raw body if the server didn't send its usual error
+/// structure
+fn response_error(status: StatusCode, body: String) -> Error {
+    let response: ErrorResponse = match serde_json::from_str(body.as_str()) {
+        Ok(response) => response,
+        Err(_) => return ErrorKind::RequestError(body).into(),
+    };
+
+    let vetoes = response.vetoes();
+    if !vetoes.is_empty() {
+        return ErrorKind::MergeVetoed(vetoes.join("\n")).into();
+    }
+
+    // other conflicts, like a pull request that already exists, also get a 409
+    let messages = response.messages();
+    if status == StatusCode::Conflict && response.is_out_of_date() {
+        return ErrorKind::VersionConflict(messages.join("\n")).into();
+    }
+
+    if messages.is_empty() {
+        return ErrorKind::RequestError(body).into();
+    }
+    ErrorKind::RequestError(messages.join("\n")).into()
+}
+
+pub fn get_self_url(pull_request: &PullRequest) -> Result<Url> {
     if let Some(link) = pull_request.self_link() {
         let url = Url::parse(&link)?;
         Ok(url)
@@ -166,3 +680,70 @@ fn get_self_url(pull_request: &PullRequest) -> Result<Url> {
         Err(ErrorKind::MissingSelfLink.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_kind(status: StatusCode, body: &str) -> ErrorKind {
+        response_error(status, body.to_string()).0
+    }
+
+    #[test]
+    fn response_errors() {
+        let out_of_date = r#"{"errors": [{
+            "message": "You are attempting to modify a pull request based on out-of-date information.",
+            "exceptionName": "com.atlassian.bitbucket.pull.PullRequestOutOfDateException"
+        }]}"#;
+        match error_kind(StatusCode::Conflict, out_of_date) {
+            ErrorKind::VersionConflict(_) => {}
+            kind => panic!("expected a version conflict, got {:?}", kind),
+        }
+
+        let duplicate = r#"{"errors": [{
+            "message": "Only one pull request may be open for a given source and target branch",
+            "exceptionName": "com.atlassian.bitbucket.pull.DuplicatePullRequestException"
+        }]}"#;
+        match error_kind(StatusCode::Conflict, duplicate) {
+            ErrorKind::RequestError(ref message) => assert!(message.starts_with("Only one")),
+            kind => panic!("expected a request error, got {:?}", kind),
+        }
+
+        let no_messages = r#"{"errors": [{"context": null}]}"#;
+        match error_kind(StatusCode::BadRequest, no_messages) {
+            ErrorKind::RequestError(ref body) => assert_eq!(no_messages, body),
+            kind => panic!("expected a request error, got {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn pull_request_ref_from_project_url() {
+        let pr = PullRequestRef::from_url(
+            "https://bitbucket.example.com/projects/PROJ/repos/my-repo/pull-requests/12/overview",
+        ).unwrap();
+        assert_eq!("PROJ", pr.project);
+        assert_eq!("my-repo", pr.slug);
+        assert_eq!(12, pr.id);
+    }
+
+    #[test]
+    fn pull_request_ref_from_personal_url() {
+        let pr = PullRequestRef::from_url(
+            "https://bitbucket.example.com/stash/users/foo/repos/my-repo/pull-requests/3",
+        ).unwrap();
+        assert_eq!("~foo", pr.project);
+        assert_eq!("my-repo", pr.slug);
+        assert_eq!(3, pr.id);
+    }
+
+    #[test]
+    fn pull_request_ref_from_invalid_url() {
+        assert!(PullRequestRef::from_url("https://bitbucket.example.com/projects/PROJ").is_err());
+        assert!(PullRequestRef::from_url("not a url").is_err());
+        assert!(
+            PullRequestRef::from_url(
+                "https://bitbucket.example.com/projects/PROJ/repos/r/pull-requests/abc"
+            ).is_err()
+        );
+    }
+}
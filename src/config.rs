@@ -3,21 +3,27 @@ use std::fs::File;
 use std::path::Path;
 use std::io::{Read, Write};
 
-use prettytable::Table;
-use prettytable::row::Row;
-use prettytable::cell::Cell;
-use prettytable::format;
+use serde_json::Value;
 use yaml_rust::{Yaml, YamlLoader};
 
+use credentials::AuthConfig;
+use description::{DescriptionMode, IssueKeys};
 use error::{Error, ErrorKind, Result};
+use git;
+use output;
+use output::{Options, Tabular};
+use util;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Project {
     pub source_project: String,
     pub source_slug: String,
     pub target_project: String,
     pub target_slug: String,
-    pub target_branch: String,
+    /// Looked up from the server when not configured
+    pub target_branch: Option<String>,
+    /// Overrides the repo's .bitbucket/pull_request_template.md
+    pub description_template: Option<String>,
 }
 
 impl Project {
@@ -28,7 +34,10 @@ impl Project {
         let target_project =
             unpack("target_project", || data["target_project"].as_str())?.to_string();
         let target_slug = unpack("target_slug", || data["target_slug"].as_str())?.to_string();
-        let target_branch = unpack("target_branch", || data["target_branch"].as_str())?.to_string();
+        let target_branch = data["target_branch"].as_str().map(|b| b.to_string());
+        let description_template = data["description_template"]
+            .as_str()
+            .map(|t| t.to_string());
 
         Ok(Project {
             source_project: source_project,
@@ -36,19 +45,40 @@ impl Project {
             target_project: target_project,
             target_slug: target_slug,
             target_branch: target_branch,
+            description_template: description_template,
         })
     }
 }
 
+/// The profile name used for a server configured at the top level of the
+/// file rather than under `servers`
+pub const DEFAULT_PROFILE: &'static str = "default";
+
+const DEFAULT_TARGET_REMOTE: &'static str = "upstream";
+
 #[derive(Debug)]
 pub struct Config {
+    /// The name of the server profile in use
+    pub profile: String,
     pub server: String,
-    pub auth: String,
+    pub auth: AuthConfig,
     pub target_branch_checking: bool,
+    /// Use the closest remote branch to HEAD as the target branch instead
+    /// of the repo's default branch when none is configured
+    pub infer_target_branch: bool,
     pub open_in_browser: bool,
+    /// Push the current branch before opening a pull request
+    pub push: bool,
+    /// How pull request descriptions are built from the branch's commits
+    pub description_mode: DescriptionMode,
+    pub issues: IssueKeys,
     pub browser_command: String,
+    /// The remote treated as the target when a project isn't configured
+    pub target_remote: String,
     pub projects: HashMap<String, Project>,
     pub groups: BTreeMap<String, HashSet<String>>,
+    /// The table columns to show for each listing command
+    pub columns: HashMap<String, Vec<String>>,
 }
 
 fn unpack<F, T>(key: &str, f: F) -> Result<T>
@@ -59,24 +89,66 @@ where
 }
 
 impl Config {
-    pub fn from_file(path: &Path) -> Result<Config> {
+    /// Loads the config for the given server profile. Without one, the
+    /// profile whose server matches a git remote of the current repo is used.
+    pub fn from_file(path: &Path, profile: Option<&str>) -> Result<Config> {
         let mut file = File::open(path)?;
         let mut content = String::new();
         file.read_to_string(&mut content)?;
         let docs = YamlLoader::load_from_str(&content)?;
-        let data = &docs[0];
 
-        let server = unpack("server", || data["server"].as_str())?.to_string();
-        let auth = unpack("auth_token", || data["auth_token"].as_str())?.to_string();
+        // not being in a git repo just means there's nothing to match against
+        let remote_hosts: Vec<String> = git::remote_urls()
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|url| util::remote_host(url))
+            .collect();
+
+        Config::from_data(&docs[0], profile, &remote_hosts)
+    }
+
+    fn from_data(data: &Yaml, profile: Option<&str>, remote_hosts: &[String]) -> Result<Config> {
+        let mut profiles = BTreeMap::new();
+        if !data["server"].is_badvalue() {
+            profiles.insert(DEFAULT_PROFILE.to_string(), data);
+        }
+        if let Some(servers) = data["servers"].as_hash() {
+            for (key, value) in servers {
+                let name = unpack("servers", || key.as_str())?.to_string();
+                profiles.insert(name, value);
+            }
+        }
+
+        let profile = select_profile(
+            &profiles,
+            profile,
+            data["default_profile"].as_str(),
+            remote_hosts,
+        )?;
+        let profile_data = profiles[&profile];
+
+        let server = unpack("server", || profile_data["server"].as_str())?.to_string();
+        let auth = AuthConfig::from_data(profile_data)?;
         let target_branch_checking =
             unpack("target_branch_checking", || data["target_branch_checking"].as_bool()).unwrap_or(false);
+        let infer_target_branch = data["infer_target_branch"].as_bool().unwrap_or(false);
         let open_in_browser =
             unpack("open_in_browser", || data["open_in_browser"].as_bool()).unwrap_or(false);
+        let push = data["push"].as_bool().unwrap_or(false);
+        let description_mode = match data["description_mode"].as_str() {
+            Some(mode) => DescriptionMode::from_name(mode)?,
+            None => DescriptionMode::Head,
+        };
+        let issues = IssueKeys::from_data(&data["issues"])?;
         let browser_command =
             unpack("browser_command", || data["browser_command"].as_str())?.to_string();
+        let target_remote = data["target_remote"]
+            .as_str()
+            .unwrap_or(DEFAULT_TARGET_REMOTE)
+            .to_string();
 
         // Projects
-        let projects_raw = unpack("projects", || data["projects"].as_hash())?;
+        let projects_raw = unpack("projects", || profile_data["projects"].as_hash())?;
         let mut projects = HashMap::new();
 
         for (key, value) in projects_raw {
@@ -86,7 +158,7 @@ impl Config {
         }
 
         // Groups
-        let groups_raw = unpack("reviewer_groups", || data["reviewer_groups"].as_hash())?;
+        let groups_raw = unpack("reviewer_groups", || profile_data["reviewer_groups"].as_hash())?;
         let mut groups = BTreeMap::new();
 
         let empty_group: HashSet<String> = HashSet::new();
@@ -105,21 +177,48 @@ impl Config {
             groups.insert(name, group);
         }
 
+        // Columns
+        let mut columns = HashMap::new();
+        if let Some(columns_raw) = data["columns"].as_hash() {
+            for (key, value) in columns_raw {
+                let command = unpack("columns", || key.as_str())?.to_string();
+                let names = match *value {
+                    Yaml::String(ref names) => output::parse_columns(names),
+                    Yaml::Array(ref names) => {
+                        let mut parsed = Vec::new();
+                        for name in names {
+                            parsed.push(unpack("columns", || name.as_str())?.to_string());
+                        }
+                        parsed
+                    }
+                    _ => return Err(ErrorKind::InvalidConfig("columns".to_string()).into()),
+                };
+                columns.insert(command, names);
+            }
+        }
+
         Ok(Config {
+            profile: profile,
             server: server,
             auth: auth,
             target_branch_checking: target_branch_checking,
+            infer_target_branch: infer_target_branch,
             open_in_browser: open_in_browser,
+            push: push,
+            description_mode: description_mode,
+            issues: issues,
             browser_command: browser_command,
+            target_remote: target_remote,
             projects: projects,
             groups: groups,
+            columns: columns,
         })
     }
 
     pub fn create_file(
         path: &Path,
         server: &str,
-        auth: &str,
+        auth: &AuthConfig,
         project_name: &str,
         source_project: &str,
         source_slug: &str,
@@ -130,24 +229,240 @@ impl Config {
         let mut file = try!(File::create(&path));
         let content = format!(
             "# configuration for bitbucket-cli
-server: \"{server}\"
-auth_token: \"{auth}\"
-
+{profile}
 # Default value for whether or not we should ensure the branch we are currently
 # opening a pull request from does not exist on the target (which may break fork
 # syncing). Setting this to true will reject opening a pr if the branch we are
 # creating the pull request from already exists on the target.
 target_branch_checking: false
 
+# When a project has no target_branch, the target repo's default branch is
+# used. Setting this to true uses the remote branch closest to HEAD (by
+# merge-base) instead, which suits stacked or release branches. Also available
+# as `bb pr --infer-branch`.
+infer_target_branch: false
+
 # default value for open in browser
 open_in_browser: false
 
+# default value for pushing the current branch before opening a pull request.
+# Without it, bb pr refuses to open a pull request for a branch with unpushed
+# commits.
+push: false
+
+# How bb pr builds a description from the commits on the branch when none is
+# given: head (the message of the latest commit), list (a bulleted list of
+# every commit summary) or full (every commit message). Ignored when the repo
+# has a description template. Also available as --description-mode, which
+# takes precedence over the template.
+description_mode: head
+
+# Issue keys are found in the branch name and commit messages. When this
+# section is present, they are added to the pull request title and linked
+# from the description. The pattern is a regex; if it has a capture group,
+# the group is the key. Title placeholders are {{{{keys}}}} and {{{{title}}}}.
+# issues:
+#   pattern: \"(?:^|[^A-Za-z0-9])([A-Z][A-Z0-9]+-[0-9]+)(?:[^A-Za-z0-9]|$)\"
+#   title_format: \"{{{{keys}}}} {{{{title}}}}\"
+#   link: \"https://jira.example.com/browse/{{{{key}}}}\"
+
 # This is executed as <browser_command> <pull request url>
 browser_command: \"google-chrome\"
 
+# Repos without an entry under projects are detected from their git remotes:
+# origin is the source and this remote (or origin, if it doesn't exist) is the
+# target.
+target_remote: upstream
+
+# The columns shown by each listing command can be changed here, either as a
+# list or a comma separated string. --columns on the command line takes
+# precedence. Run a command with --output json to see every available field.
+# columns:
+#   list: [id, title, author, reviewers, approvals, updated]
+#   reviewing: id,title,author,target
+
+# Additional servers can be configured as named profiles, each with their own
+# server, auth, projects and reviewer_groups. The server above is the profile
+# named \"default\". A profile is chosen with --profile or BB_PROFILE, or
+# automatically by matching the host of the current repo's git remotes.
+# Running `bb setup` again adds a profile to this section.
+# servers:
+#   other:
+#     server: \"https://bitbucket.example.com\"
+#     ...
+",
+            profile = profile_section(
+                server,
+                auth,
+                project_name,
+                source_project,
+                source_slug,
+                target_project,
+                target_slug,
+                target_branch
+            )
+        );
+
+        try!(file.write_all(content.as_bytes()));
+        Ok(())
+    }
+
+    /// Adds a server profile to an existing config file, keeping everything
+    /// else (including comments) as it is
+    pub fn add_profile(
+        path: &Path,
+        name: &str,
+        server: &str,
+        auth: &AuthConfig,
+        project_name: &str,
+        source_project: &str,
+        source_slug: &str,
+        target_project: &str,
+        target_slug: &str,
+        target_branch: &str,
+    ) -> Result<()> {
+        let mut content = String::new();
+        File::open(path)?.read_to_string(&mut content)?;
+
+        let docs = YamlLoader::load_from_str(&content)?;
+        let data = &docs[0];
+        let exists = match data["servers"].as_hash() {
+            Some(servers) => servers.contains_key(&Yaml::String(name.to_string())),
+            None => false,
+        };
+        if exists || (name == DEFAULT_PROFILE && !data["server"].is_badvalue()) {
+            return Err(ErrorKind::ProfileExists(name.to_string()).into());
+        }
+
+        let section = profile_section(
+            server,
+            auth,
+            project_name,
+            source_project,
+            source_slug,
+            target_project,
+            target_slug,
+            target_branch,
+        );
+        let profile = format!("  {}:\n{}", name, indent(&section, "    "));
+
+        let content = match content.lines().position(|l| l.trim_right() == "servers:") {
+            Some(index) => {
+                let mut lines: Vec<&str> = content.lines().collect();
+                lines.insert(index + 1, &profile);
+                format!("{}\n", lines.join("\n"))
+            }
+            None if data["servers"].is_badvalue() => {
+                format!("{}\nservers:\n{}", content, profile)
+            }
+            // something like `servers: {}` that can't be appended to
+            None => return Err(ErrorKind::InvalidConfig("servers".to_string()).into()),
+        };
+
+        let mut file = File::create(path)?;
+        file.write_all(content.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn get_project(&self, project: &str) -> Result<&Project> {
+        self.projects
+            .get(project)
+            .ok_or::<Error>(ErrorKind::ProjectNotFound(project.to_string()).into())
+    }
+
+    pub fn get_group(&self, group: &str) -> Result<&HashSet<String>> {
+        self.groups
+            .get(group)
+            .ok_or::<Error>(ErrorKind::GroupNotFound(group.to_string()).into())
+    }
+
+    pub fn print_groups(&self, options: &Options, force_colorize: bool) -> Result<()> {
+        output::print(&GroupList { groups: &self.groups }, options, force_colorize)
+    }
+}
+
+fn select_profile(
+    profiles: &BTreeMap<String, &Yaml>,
+    requested: Option<&str>,
+    default: Option<&str>,
+    remote_hosts: &[String],
+) -> Result<String> {
+    if let Some(name) = requested {
+        if profiles.contains_key(name) {
+            return Ok(name.to_string());
+        }
+        return Err(ErrorKind::ProfileNotFound(name.to_string()).into());
+    }
+
+    if profiles.len() == 1 {
+        return Ok(profiles.keys().next().unwrap().clone());
+    }
+
+    for host in remote_hosts {
+        let matching = profiles.iter().find(|&(_, data)| {
+            data["server"]
+                .as_str()
+                .and_then(util::remote_host)
+                .map_or(false, |server_host| server_host == *host)
+        });
+        if let Some((name, _)) = matching {
+            return Ok(name.clone());
+        }
+    }
+
+    match default {
+        Some(name) => select_profile(profiles, Some(name), None, &[]),
+        None if profiles.is_empty() => Err(ErrorKind::InvalidConfig("server".to_string()).into()),
+        None => Err(ErrorKind::AmbiguousProfile(
+            profiles.keys().cloned().collect::<Vec<String>>().join(", "),
+        ).into()),
+    }
+}
+
+fn indent(text: &str, prefix: &str) -> String {
+    text.lines()
+        .map(|line| {
+            if line.is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", prefix, line)
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The part of the config file that's specific to one server
+fn profile_section(
+    server: &str,
+    auth: &AuthConfig,
+    project_name: &str,
+    source_project: &str,
+    source_slug: &str,
+    target_project: &str,
+    target_slug: &str,
+    target_branch: &str,
+) -> String {
+    format!(
+        "server: {server}
+
+# How to authenticate with the server. The type is either token (a personal
+# HTTP access token, preferred) or basic (a username and password).
+#
+# The source says where the token or password comes from:
+#   config   stored in this file
+#   keyring  the Secret Service (secret-tool) or the macOS keychain
+#   git      `git credential fill`, using your git credential helpers
+#   command  the first line printed by password_command
+#   env      BB_TOKEN, or BB_USERNAME and BB_PASSWORD
+# BB_TOKEN always takes precedence when it is set.
+auth:
+{auth}
+
 # You can specify a list of projects here. Projects are detected via the
 # basename of the git repo directory or via a .bitbucket-proj file at
 # <git repo basename>/.bitbucket-proj. The must contain one line: the project name
+# Repos without an entry here are detected from their git remotes instead.
 projects:
   # The project name should be the same name as the repo basename (directory).
   # This enables the auto-detection. If you'd rather specify the project for
@@ -170,8 +485,15 @@ projects:
     target_slug: {target_slug}
 
     # The target branch is the branch to which the pull request will be made.
-    # This can be overwritten on the command line.
-    target_branch: {target_branch}
+    # This can be overwritten on the command line. When omitted, the target
+    # repo's default branch is looked up from the server.
+    {target_branch}
+
+    # The template for pull request descriptions, relative to the repo root.
+    # Defaults to .bitbucket/pull_request_template.md when it exists.
+    # Placeholders: {{{{commits}}}}, {{{{messages}}}}, {{{{branch}}}},
+    # {{{{target}}}}, {{{{title}}}} and {{{{ticket}}}}. HTML comments are removed.
+    # description_template: docs/pull_request_template.md
 
 # You can always specify reviewers via the command line, but these are here to
 # provide convenient sets of frequently-included reviewers. The names here are
@@ -187,46 +509,148 @@ reviewer_groups:
 #    - herp
 #    - derp
 ",
-            server = server,
-            auth = auth,
-            project_name = project_name,
-            source_project = source_project,
-            source_slug = source_slug,
-            target_project = target_project,
-            target_slug = target_slug,
-            target_branch = target_branch
-        );
+        server = util::yaml_quote(server),
+        auth = auth.to_config(),
+        project_name = project_name,
+        source_project = source_project,
+        source_slug = source_slug,
+        target_project = target_project,
+        target_slug = target_slug,
+        target_branch = if target_branch.is_empty() {
+            "# target_branch: master".to_string()
+        } else {
+            format!("target_branch: {}", target_branch)
+        }
+    )
+}
 
-        try!(file.write_all(content.as_bytes()));
-        Ok(())
-    }
+struct GroupList<'a> {
+    groups: &'a BTreeMap<String, HashSet<String>>,
+}
 
-    pub fn get_project(&self, project: &str) -> Result<&Project> {
-        self.projects
-            .get(project)
-            .ok_or::<Error>(ErrorKind::ProjectNotFound(project.to_string()).into())
+impl<'a> Tabular for GroupList<'a> {
+    fn fields(&self) -> Vec<&'static str> {
+        vec!["group", "users"]
     }
 
-    pub fn get_group(&self, group: &str) -> Result<&HashSet<String>> {
+    fn records(&self) -> Vec<Vec<Value>> {
         self.groups
-            .get(group)
-            .ok_or::<Error>(ErrorKind::GroupNotFound(group.to_string()).into())
+            .iter()
+            .map(|(name, group)| {
+                let mut users: Vec<&String> = group.iter().collect();
+                users.sort();
+                vec![
+                    Value::from(name.clone()),
+                    Value::Array(users.iter().map(|u| Value::from(u.to_string())).collect()),
+                ]
+            })
+            .collect()
     }
+}
 
-    pub fn print_groups(&self, force_colorize: bool) {
-        let mut table = Table::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    const PROFILES: &'static str = "
+browser_command: foo
+server: \"https://bitbucket.example.com\"
+auth:
+  type: token
+  token: abc
+projects: {}
+reviewer_groups: {}
+servers:
+  acquired:
+    server: \"https://git.acquired.example.com/stash\"
+    auth:
+      type: token
+      source: env
+    projects:
+      widget:
+        source_project: WID
+        source_slug: widget
+        target_project: WID
+        target_slug: widget
+        target_branch: master
+    reviewer_groups:
+      core: [bob]
+";
+
+    fn load(content: &str, profile: Option<&str>, hosts: &[&str]) -> Result<Config> {
+        let docs = YamlLoader::load_from_str(content).unwrap();
+        let hosts: Vec<String> = hosts.iter().map(|h| h.to_string()).collect();
+        Config::from_data(&docs[0], profile, &hosts)
+    }
 
-        let format = format::FormatBuilder::new().padding(1, 1).build();
+    #[test]
+    fn selecting_profile_by_name() {
+        let config = load(PROFILES, Some("acquired"), &[]).unwrap();
+        assert_eq!("acquired", config.profile);
+        assert_eq!("https://git.acquired.example.com/stash", config.server);
+        assert!(config.get_project("widget").is_ok());
+        assert!(config.get_group("core").is_ok());
+        assert_eq!("foo", config.browser_command);
 
-        table.set_format(format);
+        assert!(load(PROFILES, Some("missing"), &[]).is_err());
+    }
 
-        for (name, group) in &self.groups {
-            table.add_row(Row::new(vec![
-                Cell::new(name),
-                Cell::new(&format!("{:?}", group)),
-            ]));
-        }
+    #[test]
+    fn selecting_profile_by_remote_host() {
+        let config = load(PROFILES, None, &["github.com", "git.acquired.example.com"]).unwrap();
+        assert_eq!("acquired", config.profile);
+
+        let config = load(PROFILES, None, &["bitbucket.example.com"]).unwrap();
+        assert_eq!(DEFAULT_PROFILE, config.profile);
+    }
+
+    #[test]
+    fn ambiguous_profiles() {
+        assert!(load(PROFILES, None, &[]).is_err());
 
-        table.print_tty(force_colorize);
+        let with_default = format!("default_profile: acquired\n{}", PROFILES);
+        let config = load(&with_default, None, &[]).unwrap();
+        assert_eq!("acquired", config.profile);
+    }
+
+    fn add(path: &Path, name: &str, server: &str) -> Result<()> {
+        let auth = AuthConfig::from_data(&load_yaml("auth_token: abc"))?;
+        Config::add_profile(path, name, server, &auth, name, "A", "a", "B", "b", "dev")
+    }
+
+    fn load_yaml(content: &str) -> Yaml {
+        YamlLoader::load_from_str(content).unwrap().remove(0)
+    }
+
+    #[test]
+    fn adding_profiles() {
+        // the process id keeps concurrent test runs from sharing the file
+        let name = format!("bb-config-test-adding-profiles-{}.yml", process::id());
+        let path = env::temp_dir().join(name);
+        let auth = AuthConfig::from_data(&load_yaml("auth_token: abc")).unwrap();
+        let server = "https://one.example.com";
+        Config::create_file(&path, server, &auth, "p", "A", "a", "B", "b", "master").unwrap();
+
+        add(&path, "two", "https://two.example.com").unwrap();
+        add(&path, "three", "https://three.example.com").unwrap();
+        assert!(add(&path, "two", "https://x.example.com").is_err());
+
+        let mut content = String::new();
+        File::open(&path).unwrap().read_to_string(&mut content).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let config = load(&content, Some("two"), &[]).unwrap();
+        assert_eq!("https://two.example.com", config.server);
+        let branch = config.get_project("two").unwrap().target_branch.clone();
+        assert_eq!(Some("dev".to_string()), branch);
+        let config = load(&content, Some("three"), &[]).unwrap();
+        assert_eq!("https://three.example.com", config.server);
+        let config = load(&content, Some(DEFAULT_PROFILE), &[]).unwrap();
+        assert_eq!("https://one.example.com", config.server);
+        // comments in the original file are kept
+        assert!(content.contains("# default value for open in browser"));
     }
 }
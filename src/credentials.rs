@@ -0,0 +1,525 @@
+use std::env;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use base64::encode;
+use hyper::Url;
+use yaml_rust::Yaml;
+
+use error::{Error, ErrorKind, Result};
+use util;
+
+/// Overrides whatever is configured, which is handy for CI
+pub const TOKEN_VAR: &'static str = "BB_TOKEN";
+pub const USERNAME_VAR: &'static str = "BB_USERNAME";
+pub const PASSWORD_VAR: &'static str = "BB_PASSWORD";
+
+/// Service name used when storing secrets in the keyring
+const KEYRING_SERVICE: &'static str = "bitbucket-cli";
+
+/// How requests to the server are authenticated
+#[derive(Debug, Clone, PartialEq)]
+pub enum Auth {
+    /// HTTP Basic with base64 encoded `user:password` credentials
+    Basic(String),
+    /// A bitbucket server HTTP access token
+    Token(String),
+}
+
+impl Auth {
+    fn basic(username: &str, password: &str) -> Auth {
+        Auth::Basic(encode(&format!("{}:{}", username, password)))
+    }
+
+    /// The value of the Authorization header
+    pub fn header(&self) -> String {
+        match *self {
+            Auth::Basic(ref credentials) => format!("Basic {}", credentials),
+            Auth::Token(ref token) => format!("Bearer {}", token),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AuthType {
+    Basic,
+    Token,
+}
+
+impl AuthType {
+    pub fn from_name(name: &str) -> Result<AuthType> {
+        match name {
+            "basic" => Ok(AuthType::Basic),
+            "token" => Ok(AuthType::Token),
+            _ => Err(ErrorKind::InvalidArgument("auth.type".to_string(), name.to_string()).into()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            AuthType::Basic => "basic",
+            AuthType::Token => "token",
+        }
+    }
+}
+
+/// Where the password or token comes from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Source {
+    /// Stored in the config file itself. Basic credentials are base64
+    /// encoded `user:password`.
+    Config(String),
+    /// BB_TOKEN, or BB_USERNAME and BB_PASSWORD
+    Env,
+    /// The Secret Service (via secret-tool) or the macOS keychain
+    Keyring,
+    /// `git credential fill`, so any configured git credential helper works
+    Git,
+    /// The first line printed by a command, e.g. `pass show bitbucket`
+    Command(String),
+}
+
+impl Source {
+    pub fn from_name(name: &str) -> Result<Source> {
+        match name {
+            "env" => Ok(Source::Env),
+            "keyring" => Ok(Source::Keyring),
+            "git" => Ok(Source::Git),
+            _ => {
+                Err(ErrorKind::InvalidArgument("auth.source".to_string(), name.to_string()).into())
+            }
+        }
+    }
+}
+
+/// The auth section of the config. This says how to find credentials rather
+/// than holding them, unless the source is the config file itself.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuthConfig {
+    pub auth_type: AuthType,
+    pub username: Option<String>,
+    pub source: Source,
+}
+
+impl AuthConfig {
+    pub fn from_data(data: &Yaml) -> Result<AuthConfig> {
+        // configs created before the auth section existed only have this
+        if let Some(credentials) = data["auth_token"].as_str() {
+            return Ok(AuthConfig {
+                auth_type: AuthType::Basic,
+                username: None,
+                source: Source::Config(credentials.to_string()),
+            });
+        }
+
+        let auth = &data["auth"];
+        let auth_type = AuthType::from_name(unpack("auth.type", auth["type"].as_str())?)?;
+        let username = auth["username"].as_str().map(|u| u.to_string());
+
+        let source = match auth["source"].as_str().unwrap_or("config") {
+            "config" => {
+                let key = match auth_type {
+                    AuthType::Basic => "credentials",
+                    AuthType::Token => "token",
+                };
+                let secret = unpack(&format!("auth.{}", key), auth[key].as_str())?;
+                Source::Config(secret.to_string())
+            }
+            "command" => Source::Command(
+                unpack("auth.password_command", auth["password_command"].as_str())?.to_string(),
+            ),
+            other => Source::from_name(other)?,
+        };
+
+        // everything but the config and env sources need a username to look
+        // up basic credentials
+        if auth_type == AuthType::Basic && username.is_none() {
+            match source {
+                Source::Keyring | Source::Command(_) => {
+                    return Err(ErrorKind::InvalidConfig("auth.username".to_string()).into())
+                }
+                _ => {}
+            }
+        }
+
+        Ok(AuthConfig {
+            auth_type: auth_type,
+            username: username,
+            source: source,
+        })
+    }
+
+    /// Renders the auth section as it would appear in the config file
+    pub fn to_config(&self) -> String {
+        let mut lines = vec![format!("  type: {}", self.auth_type.name())];
+        if let Some(ref username) = self.username {
+            lines.push(format!("  username: {}", util::yaml_quote(username)));
+        }
+
+        match self.source {
+            Source::Config(ref secret) => {
+                lines.push("  source: config".to_string());
+                match self.auth_type {
+                    AuthType::Basic => {
+                        lines.push("  # base64 encoded username:password".to_string());
+                        lines.push(format!("  credentials: {}", util::yaml_quote(secret)));
+                    }
+                    AuthType::Token => {
+                        lines.push("  # a personal HTTP access token".to_string());
+                        lines.push(format!("  token: {}", util::yaml_quote(secret)));
+                    }
+                }
+            }
+            Source::Env => lines.push("  source: env".to_string()),
+            Source::Keyring => lines.push("  source: keyring".to_string()),
+            Source::Git => lines.push("  source: git".to_string()),
+            Source::Command(ref command) => {
+                lines.push("  source: command".to_string());
+                lines.push(format!("  password_command: {}", util::yaml_quote(command)));
+            }
+        }
+
+        lines.join("\n")
+    }
+
+    /// Looks up the credentials for the given server. BB_TOKEN takes
+    /// precedence over everything else.
+    pub fn resolve(&self, server: &str) -> Result<Auth> {
+        if let Ok(token) = env::var(TOKEN_VAR) {
+            return Ok(Auth::Token(token));
+        }
+
+        match self.source {
+            Source::Config(ref secret) => Ok(match self.auth_type {
+                AuthType::Basic => Auth::Basic(secret.clone()),
+                AuthType::Token => Auth::Token(secret.clone()),
+            }),
+            Source::Env => {
+                let username = env::var(USERNAME_VAR).ok().or(self.username.clone());
+                match (self.auth_type, username, env::var(PASSWORD_VAR)) {
+                    (AuthType::Basic, Some(username), Ok(password)) => {
+                        Ok(Auth::basic(&username, &password))
+                    }
+                    (AuthType::Basic, _, _) => Err(credential_error(&format!(
+                        "{} and {} must be set",
+                        USERNAME_VAR, PASSWORD_VAR
+                    ))),
+                    (AuthType::Token, _, _) => {
+                        Err(credential_error(&format!("{} must be set", TOKEN_VAR)))
+                    }
+                }
+            }
+            Source::Keyring => {
+                let secret = keyring_lookup(server, &self.keyring_account())?;
+                Ok(self.auth(self.username.as_ref(), secret))
+            }
+            Source::Git => {
+                let (username, password) = git_credential_fill(server, self.username.as_ref())?;
+                Ok(self.auth(username.as_ref(), password))
+            }
+            Source::Command(ref command) => {
+                let secret = run_password_command(command)?;
+                Ok(self.auth(self.username.as_ref(), secret))
+            }
+        }
+    }
+
+    /// Saves the secret to sources that support storing it. The config and
+    /// env sources have nothing to store.
+    pub fn store(&self, server: &str, secret: &str) -> Result<()> {
+        match self.source {
+            Source::Keyring => keyring_store(server, &self.keyring_account(), secret),
+            Source::Git => git_credential_approve(server, self.username.as_ref(), secret),
+            _ => Ok(()),
+        }
+    }
+
+    fn keyring_account(&self) -> String {
+        match self.auth_type {
+            AuthType::Basic => self.username.clone().unwrap_or_default(),
+            AuthType::Token => "token".to_string(),
+        }
+    }
+
+    fn auth(&self, username: Option<&String>, secret: String) -> Auth {
+        match (self.auth_type, username) {
+            (AuthType::Basic, Some(username)) => Auth::basic(username, &secret),
+            // without a username the secret can only be a token
+            _ => Auth::Token(secret),
+        }
+    }
+}
+
+fn unpack<'a>(key: &str, value: Option<&'a str>) -> Result<&'a str> {
+    value.ok_or::<Error>(ErrorKind::InvalidConfig(key.to_string()).into())
+}
+
+fn credential_error(message: &str) -> Error {
+    ErrorKind::CredentialError(message.to_string()).into()
+}
+
+/// Runs a command, optionally feeding it input, and returns its output
+fn run(command: &mut Command, input: Option<&str>) -> Result<String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()?;
+
+    if let Some(input) = input {
+        if let Some(ref mut stdin) = child.stdin {
+            stdin.write_all(input.as_bytes())?;
+        }
+    }
+    // close stdin so the command doesn't wait for more input
+    drop(child.stdin.take());
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(credential_error(&format!("{:?} failed", command)));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+fn run_password_command(command: &str) -> Result<String> {
+    let out = run(Command::new("sh").arg("-c").arg(command), None)?;
+    match out.lines().next() {
+        Some(line) if !line.trim().is_empty() => Ok(line.trim().to_string()),
+        _ => Err(credential_error(&format!("'{}' printed nothing", command))),
+    }
+}
+
+#[cfg(not(target_os = "macos"))]
+fn keyring_lookup(server: &str, account: &str) -> Result<String> {
+    let out = run(
+        Command::new("secret-tool").arg("lookup").args(&[
+            "service",
+            KEYRING_SERVICE,
+            "server",
+            server,
+            "account",
+            account,
+        ]),
+        None,
+    )?;
+    if out.is_empty() {
+        return Err(credential_error(&format!(
+            "no keyring entry for {}",
+            server
+        )));
+    }
+    Ok(out.trim_right_matches('\n').to_string())
+}
+
+#[cfg(not(target_os = "macos"))]
+fn keyring_store(server: &str, account: &str, secret: &str) -> Result<()> {
+    run(
+        Command::new("secret-tool")
+            .arg("store")
+            .arg(format!("--label={} ({})", KEYRING_SERVICE, server))
+            .args(&[
+                "service",
+                KEYRING_SERVICE,
+                "server",
+                server,
+                "account",
+                account,
+            ]),
+        Some(secret),
+    )
+    .map(|_| ())
+}
+
+#[cfg(target_os = "macos")]
+fn keyring_lookup(server: &str, account: &str) -> Result<String> {
+    let out = run(
+        Command::new("security")
+            .arg("find-generic-password")
+            .args(&[
+                "-s",
+                KEYRING_SERVICE,
+                "-a",
+                &keychain_account(server, account),
+                "-w",
+            ]),
+        None,
+    )?;
+    Ok(out.trim_right_matches('\n').to_string())
+}
+
+#[cfg(target_os = "macos")]
+fn keyring_store(server: &str, account: &str, secret: &str) -> Result<()> {
+    // security only takes the password as an argument, where anyone can see
+    // it in ps, so the whole command goes through stdin in interactive mode
+    let command = format!(
+        "add-generic-password -U -s {} -a {} -w {}\n",
+        security_quote(KEYRING_SERVICE),
+        security_quote(&keychain_account(server, account)),
+        security_quote(secret)
+    );
+    run(Command::new("security").arg("-i"), Some(&command)).map(|_| ())
+}
+
+/// Quotes an argument for a command given to `security -i`
+#[cfg(any(target_os = "macos", test))]
+fn security_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(target_os = "macos")]
+fn keychain_account(server: &str, account: &str) -> String {
+    format!("{}@{}", account, server)
+}
+
+/// The input `git credential` expects to describe the server
+fn git_credential_input(server: &str, username: Option<&String>) -> Result<String> {
+    let url = Url::parse(server)?;
+    let host = match url.port() {
+        Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+        None => url.host_str().unwrap_or("").to_string(),
+    };
+    let mut input = format!("protocol={}\nhost={}\n", url.scheme(), host);
+    if let Some(username) = username {
+        input.push_str(&format!("username={}\n", username));
+    }
+    Ok(input)
+}
+
+fn git_credential_fill(
+    server: &str,
+    username: Option<&String>,
+) -> Result<(Option<String>, String)> {
+    let input = git_credential_input(server, username)?;
+    let out = run(
+        Command::new("git").args(&["credential", "fill"]),
+        Some(&input),
+    )?;
+    parse_git_credential(&out)
+}
+
+fn parse_git_credential(out: &str) -> Result<(Option<String>, String)> {
+    let mut username = None;
+    let mut password = None;
+    for line in out.lines() {
+        if line.starts_with("username=") {
+            username = Some(line["username=".len()..].to_string());
+        } else if line.starts_with("password=") {
+            password = Some(line["password=".len()..].to_string());
+        }
+    }
+
+    match password {
+        Some(password) => Ok((username, password)),
+        None => Err(credential_error("git credential fill returned no password")),
+    }
+}
+
+fn git_credential_approve(server: &str, username: Option<&String>, secret: &str) -> Result<()> {
+    let input = format!(
+        "{}password={}\n",
+        git_credential_input(server, username)?,
+        secret
+    );
+    run(
+        Command::new("git").args(&["credential", "approve"]),
+        Some(&input),
+    )
+    .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn load(content: &str) -> Yaml {
+        YamlLoader::load_from_str(content).unwrap().remove(0)
+    }
+
+    #[test]
+    fn legacy_auth_token_is_basic() {
+        let auth = AuthConfig::from_data(&load("auth_token: \"Zm9vOmJhcg==\"")).unwrap();
+        assert_eq!(AuthType::Basic, auth.auth_type);
+        assert_eq!(Source::Config("Zm9vOmJhcg==".to_string()), auth.source);
+    }
+
+    #[test]
+    fn token_stored_in_config() {
+        let auth = AuthConfig::from_data(&load("auth:\n  type: token\n  token: abc123")).unwrap();
+        assert_eq!(Source::Config("abc123".to_string()), auth.source);
+        assert_eq!("Bearer abc123", Auth::Token("abc123".to_string()).header());
+    }
+
+    #[test]
+    fn external_sources() {
+        let auth = AuthConfig::from_data(&load(
+            "auth:\n  type: basic\n  username: foo\n  source: command\n  password_command: pass show bb",
+        )).unwrap();
+        assert_eq!(Source::Command("pass show bb".to_string()), auth.source);
+        assert_eq!(Some("foo".to_string()), auth.username);
+
+        let auth = AuthConfig::from_data(&load("auth:\n  type: token\n  source: keyring")).unwrap();
+        assert_eq!(Source::Keyring, auth.source);
+    }
+
+    #[test]
+    fn invalid_auth() {
+        assert!(AuthConfig::from_data(&load("auth:\n  type: token")).is_err());
+        assert!(AuthConfig::from_data(&load("auth:\n  type: kerberos")).is_err());
+        assert!(AuthConfig::from_data(&load("auth:\n  type: token\n  source: vault")).is_err());
+        // the keyring entry is found by username
+        assert!(AuthConfig::from_data(&load("auth:\n  type: basic\n  source: keyring")).is_err());
+        assert!(AuthConfig::from_data(&load("server: foo")).is_err());
+    }
+
+    #[test]
+    fn generated_auth_config_round_trips() {
+        let configs = vec![
+            AuthConfig {
+                auth_type: AuthType::Token,
+                username: None,
+                source: Source::Config("abc123".to_string()),
+            },
+            AuthConfig {
+                auth_type: AuthType::Basic,
+                username: Some("foo".to_string()),
+                source: Source::Command(r#"pass show "bb\work""#.to_string()),
+            },
+            AuthConfig {
+                auth_type: AuthType::Basic,
+                username: Some("fo\"o".to_string()),
+                source: Source::Git,
+            },
+        ];
+        for auth in configs {
+            let data = load(&format!("auth:\n{}", auth.to_config()));
+            assert_eq!(auth, AuthConfig::from_data(&data).unwrap());
+        }
+    }
+
+    #[test]
+    fn quoting_security_arguments() {
+        assert_eq!(r#""s3cret""#, security_quote("s3cret"));
+        assert_eq!(r#""a \"b\" \\c""#, security_quote(r#"a "b" \c"#));
+    }
+
+    #[test]
+    fn parsing_git_credentials() {
+        let (username, password) =
+            parse_git_credential("protocol=https\nhost=example.com\nusername=foo\npassword=bar\n")
+                .unwrap();
+        assert_eq!(Some("foo".to_string()), username);
+        assert_eq!("bar", password);
+        assert!(parse_git_credential("protocol=https\n").is_err());
+    }
+
+    #[test]
+    fn git_credential_input_includes_port() {
+        let foo = "foo".to_string();
+        assert_eq!(
+            "protocol=https\nhost=example.com:7990\nusername=foo\n",
+            git_credential_input("https://example.com:7990/stash", Some(&foo)).unwrap()
+        );
+    }
+}
@@ -0,0 +1,381 @@
+use regex::Regex;
+use yaml_rust::Yaml;
+
+use error::{ErrorKind, Result};
+use git::CommitMessage;
+
+/// Not `\b`, which doesn't count `_` as a boundary (e.g. in
+/// feature/PROJ-1234_fix_login)
+const DEFAULT_ISSUE_PATTERN: &'static str =
+    r"(?:^|[^A-Za-z0-9])([A-Z][A-Z0-9]+-[0-9]+)(?:[^A-Za-z0-9]|$)";
+const DEFAULT_TITLE_FORMAT: &'static str = "{{keys}} {{title}}";
+
+/// How a pull request description is built from the branch's commits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DescriptionMode {
+    /// The full message of the HEAD commit
+    Head,
+    /// A bulleted list of every commit summary
+    List,
+    /// Every commit message, one after the other
+    Full,
+}
+
+impl DescriptionMode {
+    pub fn from_name(name: &str) -> Result<DescriptionMode> {
+        match name {
+            "head" => Ok(DescriptionMode::Head),
+            "list" => Ok(DescriptionMode::List),
+            "full" => Ok(DescriptionMode::Full),
+            _ => Err(ErrorKind::InvalidArgument("description mode".to_string(), name.to_string()).into()),
+        }
+    }
+}
+
+/// Builds a description from the branch's commits, oldest first
+pub fn describe(commits: &[CommitMessage], mode: DescriptionMode) -> String {
+    match mode {
+        DescriptionMode::Head => commits
+            .last()
+            .map(|commit| commit.message.trim().to_string())
+            .unwrap_or_default(),
+        DescriptionMode::List => commits
+            .iter()
+            .map(|commit| format!("- {}", commit.summary))
+            .collect::<Vec<String>>()
+            .join("\n"),
+        DescriptionMode::Full => commits
+            .iter()
+            .map(|commit| commit.message.trim().to_string())
+            .collect::<Vec<String>>()
+            .join("\n\n"),
+    }
+}
+
+/// What the placeholders in a description template are replaced with
+pub struct TemplateValues<'a> {
+    pub commits: &'a [CommitMessage],
+    pub branch: &'a str,
+    pub target: &'a str,
+    pub title: &'a str,
+    /// Issue keys found in the branch and commits
+    pub keys: &'a [String],
+}
+
+/// Expands `{{commits}}`, `{{messages}}`, `{{branch}}`, `{{target}}`,
+/// `{{title}}` and `{{ticket}}` (the first issue key). Anything else is left
+/// alone, since the template may legitimately contain braces.
+pub fn expand_template(template: &str, values: &TemplateValues) -> String {
+    let ticket = values.keys.first().map(|k| k.as_str()).unwrap_or("");
+
+    template
+        .replace("{{commits}}", &describe(values.commits, DescriptionMode::List))
+        .replace("{{messages}}", &describe(values.commits, DescriptionMode::Full))
+        .replace("{{branch}}", values.branch)
+        .replace("{{target}}", values.target)
+        .replace("{{title}}", values.title)
+        .replace("{{ticket}}", ticket)
+}
+
+/// Removes HTML comments, which templates use for instructions the same way
+/// git uses # lines in commit messages. Lines left empty by this are dropped.
+pub fn strip_comments(text: &str) -> String {
+    let mut out = Vec::new();
+    let mut in_comment = false;
+
+    for line in text.lines() {
+        let mut kept = String::new();
+        let mut rest = line;
+        loop {
+            if in_comment {
+                match rest.find("-->") {
+                    Some(end) => {
+                        rest = &rest[end + 3..];
+                        in_comment = false;
+                    }
+                    None => break,
+                }
+            } else {
+                match rest.find("<!--") {
+                    Some(start) => {
+                        kept.push_str(&rest[..start]);
+                        rest = &rest[start + 4..];
+                        in_comment = true;
+                    }
+                    None => {
+                        kept.push_str(rest);
+                        break;
+                    }
+                }
+            }
+        }
+
+        // only drop lines that had nothing but a comment on them
+        if kept.trim().is_empty() && kept.len() != line.len() {
+            continue;
+        }
+        out.push(kept.trim_right().to_string());
+    }
+
+    out.join("\n").trim().to_string()
+}
+
+/// Finds issue keys (e.g. Jira's PROJ-1234) and, when configured, adds them
+/// to pull request titles and links them from descriptions
+#[derive(Debug)]
+pub struct IssueKeys {
+    /// If the pattern has a capture group, it's the key
+    pattern: Regex,
+    /// Adds keys to titles, with `{{keys}}` and `{{title}}` placeholders
+    title_format: Option<String>,
+    /// The url of an issue, with a `{{key}}` placeholder
+    link: Option<String>,
+}
+
+impl IssueKeys {
+    /// Without an issues section, keys are found with a pattern matching Jira
+    /// keys but titles and descriptions are left alone
+    pub fn from_data(data: &Yaml) -> Result<IssueKeys> {
+        if data.is_badvalue() {
+            return IssueKeys::new(DEFAULT_ISSUE_PATTERN, None, None);
+        }
+
+        IssueKeys::new(
+            data["pattern"].as_str().unwrap_or(DEFAULT_ISSUE_PATTERN),
+            Some(data["title_format"].as_str().unwrap_or(DEFAULT_TITLE_FORMAT)),
+            data["link"].as_str(),
+        )
+    }
+
+    fn new(pattern: &str, title_format: Option<&str>, link: Option<&str>) -> Result<IssueKeys> {
+        let pattern = Regex::new(pattern).map_err(|e| {
+            ErrorKind::InvalidArgument("issues.pattern".to_string(), e.to_string())
+        })?;
+        Ok(IssueKeys {
+            pattern: pattern,
+            title_format: title_format.map(|f| f.to_string()),
+            link: link.map(|l| l.to_string()),
+        })
+    }
+
+    /// Every distinct key in the texts, in the order they're found. Searching
+    /// resumes right after each key rather than after the whole match, so
+    /// keys separated by a single character can share it as a boundary.
+    pub fn find(&self, texts: &[&str]) -> Vec<String> {
+        let mut keys: Vec<String> = Vec::new();
+        let mut locations = self.pattern.capture_locations();
+        for text in texts {
+            let mut start = 0;
+            while start <= text.len() {
+                if self.pattern.captures_read_at(&mut locations, text, start).is_none() {
+                    break;
+                }
+                let (key_start, key_end) = match locations.get(1).or(locations.get(0)) {
+                    Some(key) => key,
+                    None => break,
+                };
+
+                let key = text[key_start..key_end].to_string();
+                if !key.is_empty() && !keys.contains(&key) {
+                    keys.push(key);
+                }
+
+                start = if key_end > start {
+                    key_end
+                } else {
+                    // an empty match, step over one character
+                    match text[start..].chars().next() {
+                        Some(c) => start + c.len_utf8(),
+                        None => break,
+                    }
+                };
+            }
+        }
+        keys
+    }
+
+    /// Adds the keys that aren't already in the title
+    pub fn title(&self, title: &str, keys: &[String]) -> String {
+        let missing: Vec<&str> = keys.iter()
+            .filter(|key| !title.contains(key.as_str()))
+            .map(|key| key.as_str())
+            .collect();
+
+        match self.title_format {
+            Some(ref format) if !missing.is_empty() => format
+                .replace("{{keys}}", &missing.join(" "))
+                .replace("{{title}}", title),
+            _ => title.to_string(),
+        }
+    }
+
+    /// Appends links to the issues that the description doesn't link yet
+    pub fn link(&self, description: &str, keys: &[String]) -> String {
+        let link = match self.link {
+            Some(ref link) => link,
+            None => return description.to_string(),
+        };
+
+        let links: Vec<String> = keys.iter()
+            .map(|key| (key, link.replace("{{key}}", key)))
+            .filter(|&(_, ref url)| !description.contains(url.as_str()))
+            .map(|(key, url)| format!("- [{}]({})", key, url))
+            .collect();
+
+        if links.is_empty() {
+            description.to_string()
+        } else if description.is_empty() {
+            links.join("\n")
+        } else {
+            format!("{}\n\n{}", description, links.join("\n"))
+        }
+    }
+}
+
+/// The summary of the only commit on the branch, or the branch name made
+/// readable if there are several
+pub fn default_title(commits: &[CommitMessage], branch: &str) -> String {
+    if commits.len() == 1 {
+        commits[0].summary.clone()
+    } else {
+        humanize_branch(branch)
+    }
+}
+
+/// Turns `feature/add-login_page` into `Add login page`
+pub fn humanize_branch(branch: &str) -> String {
+    let name = branch.rsplit('/').next().unwrap_or(branch);
+    let words: Vec<&str> = name.split(|c| c == '-' || c == '_')
+        .filter(|word| !word.is_empty())
+        .collect();
+    let sentence = words.join(" ");
+
+    let mut chars = sentence.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => branch.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commits() -> Vec<CommitMessage> {
+        vec![
+            CommitMessage::new("Add the widget\n\nIt does things.\n"),
+            CommitMessage::new("Fix the widget\n"),
+        ]
+    }
+
+    #[test]
+    fn describing_commits() {
+        let commits = commits();
+        assert_eq!("Fix the widget", describe(&commits, DescriptionMode::Head));
+        assert_eq!(
+            "- Add the widget\n- Fix the widget",
+            describe(&commits, DescriptionMode::List)
+        );
+        assert_eq!(
+            "Add the widget\n\nIt does things.\n\nFix the widget",
+            describe(&commits, DescriptionMode::Full)
+        );
+    }
+
+    #[test]
+    fn default_titles() {
+        let commits = commits();
+        assert_eq!("Add the widget", default_title(&commits[..1], "feature/widget"));
+        assert_eq!("Widget fixes", default_title(&commits, "feature/widget-fixes"));
+    }
+
+    #[test]
+    fn expanding_templates() {
+        let commits = commits();
+        let values = TemplateValues {
+            commits: &commits,
+            branch: "feature/WID-42-widget",
+            target: "master",
+            title: "Widget",
+            keys: &["WID-42".to_string()],
+        };
+        let template = "## Summary ({{ticket}})\n{{commits}}\n\n{{branch}} -> {{target}}: {{other}}";
+        assert_eq!(
+            "## Summary (WID-42)\n- Add the widget\n- Fix the widget\n\nfeature/WID-42-widget -> master: {{other}}",
+            expand_template(template, &values)
+        );
+    }
+
+    #[test]
+    fn stripping_comments() {
+        let text = "## Summary\n<!-- what does\nthis change? -->\nStuff <!-- inline -->\n\n## Risk\n<!-- low? -->\nNone\n";
+        assert_eq!("## Summary\nStuff\n\n## Risk\nNone", strip_comments(text));
+    }
+
+    #[test]
+    fn finding_issue_keys() {
+        let keys = IssueKeys::from_data(&Yaml::BadValue).unwrap();
+        assert_eq!(
+            vec!["ABC-123".to_string(), "XY9-1".to_string()],
+            keys.find(&["feature/ABC-123-thing", "Fix XY9-1 and ABC-123", "xABC-12 abc-1"])
+        );
+        assert_eq!(
+            vec!["PROJ-1234".to_string()],
+            keys.find(&["feature/PROJ-1234_fix_login", "PROJ-1234x"])
+        );
+        assert_eq!(
+            vec!["ABC-1".to_string(), "ABC-2".to_string()],
+            keys.find(&["ABC-1_ABC-2"])
+        );
+
+        // the capture group is the key
+        let keys = IssueKeys::new(r"#([0-9]+)", None, None).unwrap();
+        assert_eq!(vec!["42".to_string()], keys.find(&["fixes #42"]));
+
+        assert!(IssueKeys::new("(", None, None).is_err());
+    }
+
+    #[test]
+    fn adding_issue_keys_to_titles() {
+        let keys = vec!["ABC-1".to_string(), "ABC-2".to_string()];
+
+        let unconfigured = IssueKeys::from_data(&Yaml::BadValue).unwrap();
+        assert_eq!("Fix it", unconfigured.title("Fix it", &keys));
+
+        let configured = IssueKeys::new(DEFAULT_ISSUE_PATTERN, Some("[{{keys}}] {{title}}"), None)
+            .unwrap();
+        assert_eq!("[ABC-1 ABC-2] Fix it", configured.title("Fix it", &keys));
+        assert_eq!("[ABC-2] ABC-1: Fix it", configured.title("ABC-1: Fix it", &keys));
+        assert_eq!("Fix it", configured.title("Fix it", &[]));
+    }
+
+    #[test]
+    fn linking_issues() {
+        let keys = vec!["ABC-1".to_string(), "ABC-2".to_string()];
+        let issues = IssueKeys::new(
+            DEFAULT_ISSUE_PATTERN,
+            None,
+            Some("https://jira.example.com/browse/{{key}}"),
+        ).unwrap();
+
+        assert_eq!(
+            "See https://jira.example.com/browse/ABC-1\n\n- [ABC-2](https://jira.example.com/browse/ABC-2)",
+            issues.link("See https://jira.example.com/browse/ABC-1", &keys)
+        );
+        assert_eq!(
+            "- [ABC-1](https://jira.example.com/browse/ABC-1)",
+            issues.link("", &keys[..1])
+        );
+        assert_eq!(
+            "Stuff",
+            IssueKeys::from_data(&Yaml::BadValue).unwrap().link("Stuff", &keys)
+        );
+    }
+
+    #[test]
+    fn humanizing_branches() {
+        assert_eq!("Add login page", humanize_branch("feature/add-login_page"));
+        assert_eq!("Widget", humanize_branch("widget"));
+        assert_eq!("--", humanize_branch("--"));
+    }
+}
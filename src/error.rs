@@ -25,10 +25,6 @@ error_chain! {
     }
 
     errors {
-        DryRun {
-            description("dry run not a real error")
-            display("dry run not a real error")
-        }
         RepoEmpty {
             description("repo is empty")
             display("repo is empty")
@@ -45,6 +41,18 @@ error_chain! {
             description("request error")
             display("request error. response: {}", response)
         }
+        MergeVetoed(vetoes: String) {
+            description("merge vetoed")
+            display("the merge was vetoed:\n{}", vetoes)
+        }
+        MergeConflicted {
+            description("pull request has conflicts")
+            display("the pull request has conflicts with its target branch, merge or rebase it first")
+        }
+        VersionConflict(message: String) {
+            description("pull request was modified on the server")
+            display("the pull request was modified on the server, please try again: {}", message)
+        }
         MissingSelfLink {
             description("response missing self link")
             display("response missing self link")
@@ -57,14 +65,82 @@ error_chain! {
             description("project not found")
             display("project not found: {}", project)
         }
+        ProfileNotFound(profile: String) {
+            description("server profile not found")
+            display("server profile not found: {}", profile)
+        }
+        AmbiguousProfile(profiles: String) {
+            description("could not pick a server profile")
+            display("could not pick a server profile, use --profile or BB_PROFILE to choose one of: {}", profiles)
+        }
+        ProfileExists(profile: String) {
+            description("server profile already exists")
+            display("server profile already exists: {}", profile)
+        }
+        InvalidRemoteUrl(url: String) {
+            description("could not determine the repository from a git remote")
+            display("could not determine the project and repository from git remote {}", url)
+        }
         GroupNotFound(group: String) {
             description("group not found")
             display("group not found: {}", group)
         }
+        InvalidReviewers(reviewers: String) {
+            description("some reviewers can't be added")
+            display("some reviewers can't be added, fix them or use --skip-invalid-reviewers to leave them out:\n{}", reviewers)
+        }
         InvalidPullRequest(reason: String) {
             description("invalid pull request")
             display("invalid pull request: {}", reason)
         }
+        PullRequestNotFound(state: String, branch: String) {
+            description("no pull request found for branch")
+            display("no {} pull request found from branch '{}'", state, branch)
+        }
+        InvalidPullRequestId(id: String) {
+            description("invalid pull request id")
+            display("invalid pull request id: {}", id)
+        }
+        InvalidCommentId(id: String) {
+            description("invalid comment id")
+            display("invalid comment id: {}", id)
+        }
+        InvalidLineNumber(line: String) {
+            description("invalid line number")
+            display("invalid line number: {}", line)
+        }
+        InvalidArgument(name: String, value: String) {
+            description("invalid argument")
+            display("invalid value for {}: {}", name, value)
+        }
+        DirtyWorkingTree {
+            description("working tree has uncommitted changes")
+            display("working tree has uncommitted changes, commit or stash them first")
+        }
+        LocalBranchExists(branch: String) {
+            description("local branch already exists")
+            display("local branch '{}' already exists", branch)
+        }
+        MissingCloneUrl(repo: String) {
+            description("repository has no clone url")
+            display("could not determine a clone url for {}", repo)
+        }
+        CredentialError(message: String) {
+            description("could not get credentials")
+            display("could not get credentials: {}", message)
+        }
+        OutputError(message: String) {
+            description("could not render output")
+            display("could not render output: {}", message)
+        }
+        BranchNotPushed(branch: String, remote: String) {
+            description("branch has not been pushed")
+            display("branch '{}' has not been pushed to {}, push it first or use --push", branch, remote)
+        }
+        BranchAhead(branch: String, remote: String, commits: usize) {
+            description("branch has unpushed commits")
+            display("branch '{}' is {} commit(s) ahead of {}, push it first or use --push", branch, commits, remote)
+        }
         TargetBranchExists(branch: String) {
             description("The current branch already exists on the target")
             display("The current branch '{}' already exists on the target", branch)
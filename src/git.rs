@@ -1,7 +1,10 @@
 use std::env;
 use std::path::PathBuf;
-use git2::Repository;
+use git2;
+use git2::{BranchType, FetchOptions, PushOptions, RemoteCallbacks, Repository, StatusOptions};
+use git2::build::CheckoutBuilder;
 use error::{ErrorKind, Result};
+use util;
 
 fn repository() -> Result<Repository> {
     let current_dir = env::current_dir()?;
@@ -44,28 +47,281 @@ pub fn repo_name() -> Result<String> {
     Err(ErrorKind::RepoEmpty.into())
 }
 
-pub fn commit_summary() -> Result<String> {
+/// The parts of a commit used to describe a pull request
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommitMessage {
+    pub summary: String,
+    pub message: String,
+}
+
+impl CommitMessage {
+    pub fn new(message: &str) -> CommitMessage {
+        CommitMessage {
+            summary: message.lines().next().unwrap_or("").trim().to_string(),
+            message: message.to_string(),
+        }
+    }
+
+    fn from_commit(commit: &git2::Commit) -> Result<CommitMessage> {
+        match commit.message_raw() {
+            Some(msg) => Ok(CommitMessage::new(msg)),
+            None => Err(ErrorKind::InvalidReference.into()),
+        }
+    }
+}
+
+pub fn head_commit() -> Result<CommitMessage> {
     let repo = repository()?;
     let head = repo.head()?;
-    let mut commit = match head.target() {
+    let commit = match head.target() {
         Some(oid) => repo.find_commit(oid)?,
         None => return Err(ErrorKind::InvalidReference.into()),
     };
-    match commit.summary() {
-        Some(msg) => Ok(msg.to_string()),
-        None => Err(ErrorKind::InvalidReference.into()),
+    CommitMessage::from_commit(&commit)
+}
+
+/// The commits on HEAD since its merge-base with `base` (a ref such as
+/// refs/remotes/origin/master), oldest first. None if `base` doesn't exist.
+pub fn commits_since(base: &str) -> Result<Option<Vec<CommitMessage>>> {
+    let repo = repository()?;
+    let base = match repo.refname_to_id(base) {
+        Ok(oid) => oid,
+        Err(_) => return Ok(None),
+    };
+    let head = match repo.head()?.target() {
+        Some(oid) => oid,
+        None => return Err(ErrorKind::InvalidReference.into()),
+    };
+    let merge_base = repo.merge_base(head, base)?;
+
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push(head)?;
+    revwalk.hide(merge_base)?;
+
+    let mut commits = Vec::new();
+    for oid in revwalk {
+        commits.push(CommitMessage::from_commit(&repo.find_commit(oid?)?)?);
+    }
+    // the walk starts at HEAD
+    commits.reverse();
+    Ok(Some(commits))
+}
+
+/// Builds callbacks that authenticate through the ssh agent or the user's git
+/// credential helpers, the same way the git command line would
+fn remote_callbacks<'a>(git_config: &'a git2::Config) -> RemoteCallbacks<'a> {
+    let mut attempts = 0;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        // libgit2 keeps asking for as long as we keep answering
+        attempts += 1;
+        if attempts > 3 {
+            return Err(git2::Error::from_str("authentication failed"));
+        }
+
+        if allowed.contains(git2::USERNAME) {
+            git2::Cred::username(username.unwrap_or("git"))
+        } else if allowed.contains(git2::SSH_KEY) {
+            git2::Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else {
+            git2::Cred::credential_helper(git_config, url, username)
+        }
+    });
+    callbacks
+}
+
+pub fn is_dirty() -> Result<bool> {
+    let repo = repository()?;
+    let mut options = StatusOptions::new();
+    options.include_untracked(false).include_ignored(false);
+    let statuses = repo.statuses(Some(&mut options))?;
+    Ok(!statuses.is_empty())
+}
+
+pub fn remote_url(name: &str) -> Result<Option<String>> {
+    let repo = repository()?;
+    let remote = match repo.find_remote(name) {
+        Ok(remote) => remote,
+        Err(_) => return Ok(None),
+    };
+    Ok(remote.url().map(|url| url.to_string()))
+}
+
+/// The urls of every remote, with origin first
+pub fn remote_urls() -> Result<Vec<String>> {
+    let repo = repository()?;
+    let mut names: Vec<String> = repo.remotes()?
+        .iter()
+        .filter_map(|name| name.map(|n| n.to_string()))
+        .collect();
+    names.sort_by_key(|name| name.as_str() != "origin");
+
+    let mut urls = Vec::new();
+    for name in names {
+        if let Some(url) = repo.find_remote(&name)?.url() {
+            urls.push(url.to_string());
+        }
     }
+    Ok(urls)
 }
 
-pub fn commit_message() -> Result<String> {
+/// The branch on the remote that HEAD most recently diverged from, i.e. the
+/// one whose merge-base with HEAD has the fewest commits after it
+pub fn closest_remote_branch(remote: &str) -> Result<Option<String>> {
     let repo = repository()?;
     let head = repo.head()?;
-    let commit = match head.target() {
-        Some(oid) => repo.find_commit(oid)?,
-        None => return Err(ErrorKind::InvalidReference.into()),
+    let head_oid = match head.target() {
+        Some(oid) => oid,
+        None => return Ok(None),
     };
-    match commit.message_raw() {
-        Some(msg) => Ok(msg.to_string()),
-        None => Err(ErrorKind::InvalidReference.into()),
+    let current = head.shorthand().map(|name| name.to_string());
+
+    let prefix = format!("refs/remotes/{}/", remote);
+    let mut closest: Option<(usize, String)> = None;
+    for reference in repo.references_glob(&format!("{}*", prefix))? {
+        let reference = reference?;
+        let name = match reference.name() {
+            Some(name) => name.trim_left_matches(&prefix).to_string(),
+            None => continue,
+        };
+        // the remote copy of the current branch would always win
+        if name == "HEAD" || Some(&name) == current.as_ref() {
+            continue;
+        }
+
+        let oid = match reference.target() {
+            Some(oid) => oid,
+            None => continue,
+        };
+        let base = match repo.merge_base(head_oid, oid) {
+            Ok(base) => base,
+            Err(_) => continue,
+        };
+        let (ahead, _) = repo.graph_ahead_behind(head_oid, base)?;
+        if closest.as_ref().map_or(true, |&(distance, _)| ahead < distance) {
+            closest = Some((ahead, name));
+        }
+    }
+
+    Ok(closest.map(|(_, name)| name))
+}
+
+/// Finds a remote pointing at the given bitbucket project and repo slug
+pub fn find_remote(project: &str, slug: &str) -> Result<Option<String>> {
+    let repo = repository()?;
+
+    for name in repo.remotes()?.iter() {
+        let name = match name {
+            Some(name) => name,
+            None => continue,
+        };
+        let remote = repo.find_remote(name)?;
+        let repo_of_remote = remote.url().and_then(util::parse_remote_url);
+        if let Some((ref remote_project, ref remote_slug)) = repo_of_remote {
+            if remote_project.eq_ignore_ascii_case(project)
+                && remote_slug.eq_ignore_ascii_case(slug)
+            {
+                return Ok(Some(name.to_string()));
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+pub fn add_remote(name: &str, url: &str) -> Result<()> {
+    let repo = repository()?;
+    repo.remote(name, url)?;
+    Ok(())
+}
+
+pub fn fetch(remote: &str, refspec: &str) -> Result<()> {
+    let repo = repository()?;
+    let git_config = repo.config()?;
+    let mut remote = repo.find_remote(remote)?;
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(remote_callbacks(&git_config));
+    remote.fetch(&[refspec], Some(&mut options), None)?;
+    Ok(())
+}
+
+/// How a local branch compares to its copy on a remote
+#[derive(Debug, PartialEq)]
+pub enum PushState {
+    /// The remote has no branch with this name
+    Missing,
+    /// The local branch has this many commits the remote doesn't
+    Ahead(usize),
+    UpToDate,
+}
+
+/// Compares the local branch with the commit the server says its branch is
+/// at, rather than the remote tracking branch, which is only as recent as the
+/// last fetch. The branch is fetched if the server's commit isn't known here.
+pub fn push_state(remote: &str, branch: &str, server_head: Option<&str>) -> Result<PushState> {
+    let server_head = match server_head {
+        Some(head) => head,
+        None => return Ok(PushState::Missing),
+    };
+
+    let repo = repository()?;
+    let local = repo.refname_to_id(&format!("refs/heads/{}", branch))?;
+    let upstream = git2::Oid::from_str(server_head)?;
+    if repo.find_commit(upstream).is_err() {
+        fetch(remote, &format!("+refs/heads/{0}:refs/remotes/{1}/{0}", branch, remote))?;
+    }
+
+    match repo.graph_ahead_behind(local, upstream)? {
+        (0, _) => Ok(PushState::UpToDate),
+        (ahead, _) => Ok(PushState::Ahead(ahead)),
     }
 }
+
+/// Pushes the branch to the same name on the remote, setting it as the
+/// branch's upstream if it doesn't have one yet
+pub fn push(remote_name: &str, branch: &str) -> Result<()> {
+    let repo = repository()?;
+    let git_config = repo.config()?;
+    let mut remote = repo.find_remote(remote_name)?;
+
+    let mut callbacks = remote_callbacks(&git_config);
+    // rejections (e.g. non-fast-forward) are only reported here
+    callbacks.push_update_reference(|_, status| match status {
+        Some(message) => Err(git2::Error::from_str(message)),
+        None => Ok(()),
+    });
+    let mut options = PushOptions::new();
+    options.remote_callbacks(callbacks);
+
+    let refspec = format!("refs/heads/{0}:refs/heads/{0}", branch);
+    remote.push(&[refspec.as_str()], Some(&mut options))?;
+
+    let mut local = repo.find_branch(branch, BranchType::Local)?;
+    let upstream = format!("{}/{}", remote_name, branch);
+    let tracked = repo.find_branch(&upstream, BranchType::Remote).is_ok();
+    if local.upstream().is_err() && tracked {
+        local.set_upstream(Some(&upstream))?;
+    }
+    Ok(())
+}
+
+pub fn branch_exists(name: &str) -> Result<bool> {
+    let repo = repository()?;
+    let exists = repo.find_branch(name, BranchType::Local).is_ok();
+    Ok(exists)
+}
+
+/// Creates `name` from the remote tracking branch `upstream` (such as
+/// origin/master), sets it to track `upstream` and checks it out
+pub fn checkout_tracking_branch(name: &str, upstream: &str) -> Result<()> {
+    let repo = repository()?;
+    let oid = repo.refname_to_id(&format!("refs/remotes/{}", upstream))?;
+    let commit = repo.find_commit(oid)?;
+
+    let mut branch = repo.branch(name, &commit, false)?;
+    branch.set_upstream(Some(upstream))?;
+
+    repo.checkout_tree(commit.as_object(), Some(CheckoutBuilder::new().safe()))?;
+    repo.set_head(&format!("refs/heads/{}", name))?;
+    Ok(())
+}
@@ -1,6 +1,7 @@
 #![recursion_limit = "1024"] // error chain recursion can be deep
 
 extern crate base64;
+extern crate chrono;
 #[macro_use]
 extern crate clap;
 extern crate eprompt;
@@ -9,10 +10,13 @@ extern crate error_chain;
 extern crate git2;
 extern crate hyper;
 extern crate prettytable;
+extern crate regex;
 extern crate rpassword;
+extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
+extern crate term_size;
 extern crate url;
 extern crate yaml_rust;
 
@@ -20,41 +24,115 @@ use clap::{App, Arg, ArgMatches};
 use std::collections::HashSet;
 use std::env;
 use std::io;
-use std::io::Write;
+use std::io::Read;
 use std::path::Path;
 
 use base64::encode;
 use eprompt::Prompt;
 
-use client::Bitbucket;
-use config::Config;
+use client::{Bitbucket, PullRequestFilter, PullRequestRef};
+use config::{Config, Project};
+use description::DescriptionMode;
+use credentials::{AuthConfig, AuthType, Source};
 use error::{Error, ErrorKind, Result, UnwrapOrExit};
-use bitbucket_data::PullRequest;
+use git::PushState;
+use bitbucket_data::{Anchor, Comment, MergeRequest, PullRequest, PullRequestList, Timeline};
+use output::OutputFormat;
 
 mod client;
 mod config;
+mod credentials;
+mod description;
 mod error;
 mod git;
 mod bitbucket_data;
+mod output;
+mod reviewers;
 mod util;
+mod wizard;
 
 pub fn exit(message: &str) -> ! {
     let err = clap::Error::with_description(message, clap::ErrorKind::InvalidValue);
     err.exit();
 }
 
-fn prompt(label: &str) -> Result<String> {
-    print!("{}", label);
-    io::stdout().flush()?; // need to do this since print! won't flush
-    let mut res = String::new();
-    io::stdin().read_line(&mut res)?;
-    Ok(res.trim().to_string())
-}
-
 fn setup(path: &Path) -> Result<()> {
-    let server = prompt("bitbucket server url: ")?;
-    let username = prompt("username: ")?;
-    let password = rpassword::prompt_password_stdout("password: ")?;
+    // an existing config gets this server added as a new profile instead
+    let profile = if path.exists() {
+        println!(
+            "{} already exists, so this server will be added to it as a new profile.",
+            path.display()
+        );
+        Some(util::prompt("profile name: ")?)
+    } else {
+        None
+    };
+
+    let server = util::prompt("bitbucket server url: ")?;
+
+    println!(
+        "
+Authenticating with a personal HTTP access token is preferred, since it does
+not need your password. Tokens can be created under
+Manage account > HTTP access tokens."
+    );
+    let auth_type = match util::prompt("auth type (token/basic) [token]: ")?.as_str() {
+        "" => AuthType::Token,
+        other => AuthType::from_name(other)?,
+    };
+    let username = match auth_type {
+        AuthType::Basic => Some(util::prompt("username: ")?),
+        AuthType::Token => None,
+    };
+    let secret_label = match auth_type {
+        AuthType::Basic => "password: ",
+        AuthType::Token => "token: ",
+    };
+
+    println!(
+        "
+Where should the {} be kept?
+    keyring  the Secret Service (secret-tool) or the macOS keychain
+    git      your git credential helper
+    command  a command that prints it, e.g. `pass show bitbucket`
+    env      the {} environment variable(s)
+    config   the config file, in plain text",
+        secret_label.trim_right_matches(": "),
+        match auth_type {
+            AuthType::Basic => "BB_USERNAME and BB_PASSWORD",
+            AuthType::Token => "BB_TOKEN",
+        }
+    );
+    let (source, secret) = match util::prompt("source (keyring/git/command/env/config) [keyring]: ")?.as_str() {
+        "command" => (Source::Command(util::prompt("command: ")?), None),
+        "env" => (Source::Env, None),
+        "config" => {
+            let secret = rpassword::prompt_password_stdout(secret_label)?.trim().to_string();
+            let stored = match username {
+                Some(ref username) => encode(&format!("{}:{}", username, secret)),
+                None => secret,
+            };
+            (Source::Config(stored), None)
+        }
+        name => {
+            let source = if name.is_empty() {
+                Source::Keyring
+            } else {
+                Source::from_name(name)?
+            };
+            let secret = rpassword::prompt_password_stdout(secret_label)?.trim().to_string();
+            (source, Some(secret))
+        }
+    };
+
+    let auth = AuthConfig {
+        auth_type: auth_type,
+        username: username,
+        source: source,
+    };
+    if let Some(secret) = secret {
+        auth.store(&server, &secret)?;
+    }
 
     println!(
         "
@@ -63,56 +141,68 @@ This enables the auto-detection. If you'd rather specify the project for
 a repo explicitly, create a .bitbucket-proj file containing the project
 name as specified below"
     );
-    let project_name = prompt("primary project name: ")?;
+    let project_name = util::prompt("primary project name: ")?;
 
     println!(
         "
 The source project is the project KEY or a tilde-prefixed username for
 a personal project. This is the project the pull request will be made from."
     );
-    let source_project = prompt("source project: ")?;
+    let source_project = util::prompt("source project: ")?;
 
     println!(
         "
 The source slug is the repository under the source project from which the
 pull request will be made."
     );
-    let source_slug = prompt("source slug: ")?;
+    let source_slug = util::prompt("source slug: ")?;
 
     println!(
         "
 The target project is the project KEY to which the pull request will be made."
     );
-    let target_project = prompt("target project: ")?;
+    let target_project = util::prompt("target project: ")?;
 
     println!(
         "
 The target slug is the repo within the target project to which the pull
 request will be made."
     );
-    let target_slug = prompt("target slug: ")?;
+    let target_slug = util::prompt("target slug: ")?;
 
     println!(
         "
 The target branch is the branch to which the pull request will be made.
-This can be overwritten on the command line."
+This can be overwritten on the command line. Leave it blank to use the
+target repo's default branch."
     );
-    let target_branch = prompt("target branch: ")?;
-
-    let auth = format!("{}:{}", username.trim(), password.trim());
-    let base64auth = encode(&auth);
-
-    Config::create_file(
-        path,
-        &server,
-        &base64auth,
-        &project_name,
-        &source_project,
-        &source_slug,
-        &target_project,
-        &target_slug,
-        &target_branch,
-    )?;
+    let target_branch = util::prompt("target branch: ")?;
+
+    match profile {
+        Some(ref name) => Config::add_profile(
+            path,
+            name,
+            &server,
+            &auth,
+            &project_name,
+            &source_project,
+            &source_slug,
+            &target_project,
+            &target_slug,
+            &target_branch,
+        )?,
+        None => Config::create_file(
+            path,
+            &server,
+            &auth,
+            &project_name,
+            &source_project,
+            &source_slug,
+            &target_project,
+            &target_slug,
+            &target_branch,
+        )?,
+    }
 
     println!(
         "
@@ -124,9 +214,9 @@ Please edit {} to have your desired configuration (particularly user groups)",
 }
 
 fn branch_exists_on_target(config: &Config, client: &Bitbucket, debug: bool) -> Result<()> {
-    let project = config.get_project(&util::get_project_name()?)?;
+    let project = util::current_project(config)?;
     let branch = git::current_full_branch()?;
-    if client.branch_exists(project, &branch, debug)? {
+    if client.branch_exists(&project, &branch, debug)? {
         println!("branch {} exists on target", branch);
     } else {
         println!("branch {} does not exist on target", branch);
@@ -134,8 +224,582 @@ fn branch_exists_on_target(config: &Config, client: &Bitbucket, debug: bool) ->
     Ok(())
 }
 
-fn groups(config: &Config) -> Result<()> {
-    config.print_groups(true);
+fn groups(config: &Config, matches: &ArgMatches) -> Result<()> {
+    config.print_groups(&output_options(config, matches)?, true)
+}
+
+/// Global flags may have been given after any of the nested subcommands
+/// (e.g. `bb pr view 12 --profile work`), the innermost one winning
+fn global_value<'a>(matches: &'a ArgMatches, name: &str) -> Option<&'a str> {
+    matches
+        .subcommand()
+        .1
+        .and_then(|subcmd| global_value(subcmd, name))
+        .or_else(|| matches.value_of(name))
+}
+
+fn global_flag(matches: &ArgMatches, name: &str) -> bool {
+    matches.is_present(name)
+        || matches
+            .subcommand()
+            .1
+            .map_or(false, |subcmd| global_flag(subcmd, name))
+}
+
+fn output_format(matches: &ArgMatches) -> Result<OutputFormat> {
+    match global_value(matches, "output") {
+        Some(name) => OutputFormat::from_name(name),
+        None => Ok(OutputFormat::Table),
+    }
+}
+
+/// Combines --output, --columns and --format with the columns configured for
+/// the subcommand
+fn output_options(config: &Config, matches: &ArgMatches) -> Result<output::Options> {
+    let mut options = output::Options::new(output_format(matches)?);
+
+    if let (command, Some(subcmd)) = matches.subcommand() {
+        options.columns = match subcmd.value_of("columns") {
+            Some(columns) => Some(output::parse_columns(columns)),
+            None => config.columns.get(command).cloned(),
+        };
+        options.template = subcmd.value_of("format").map(|f| f.to_string());
+    }
+
+    Ok(options)
+}
+
+/// The pull request given by id or url, or else the open pull request from
+/// the current branch
+fn resolve_pull_request(
+    config: &Config,
+    client: &Bitbucket,
+    id: Option<&str>,
+    debug: bool,
+) -> Result<PullRequestRef> {
+    resolve_pull_request_in_state(config, client, id, "OPEN", debug)
+}
+
+/// Like `resolve_pull_request`, but looks for a pull request from the current
+/// branch in the given state
+fn resolve_pull_request_in_state(
+    config: &Config,
+    client: &Bitbucket,
+    id: Option<&str>,
+    state: &str,
+    debug: bool,
+) -> Result<PullRequestRef> {
+    if let Some(id) = id {
+        if id.contains("://") {
+            return PullRequestRef::from_url(id);
+        }
+    }
+
+    let project = util::current_project(config)?;
+
+    let id = match id {
+        Some(id) => id.parse::<u64>()
+            .map_err(|_| Error::from(ErrorKind::InvalidPullRequestId(id.to_string())))?,
+        None => {
+            let branch = git::current_full_branch()?;
+            // filtered by branch on the server, rather than paging through
+            // every pull request; the source repo is still checked here since
+            // forks may have branches of the same name
+            let filter = PullRequestFilter {
+                state: state.to_string(),
+                source: Some(branch.clone()),
+                ..PullRequestFilter::default()
+            };
+            let pull_requests = client.repo_pull_requests(
+                &project.target_project,
+                &project.target_slug,
+                &filter,
+                debug,
+            )?;
+            pull_requests
+                .find_from(&branch, &project.source_project, &project.source_slug)
+                .and_then(|pr| pr.id())
+                .ok_or::<Error>(ErrorKind::PullRequestNotFound(state.to_lowercase(), branch.clone()).into())?
+        }
+    };
+
+    Ok(PullRequestRef {
+        project: project.target_project.clone(),
+        slug: project.target_slug.clone(),
+        id: id,
+    })
+}
+
+fn version_of(pull_request: &PullRequest) -> Result<u64> {
+    pull_request
+        .version()
+        .ok_or::<Error>(ErrorKind::InvalidPullRequest("Missing version".to_string()).into())
+}
+
+fn current_version(client: &Bitbucket, pr_ref: &PullRequestRef, debug: bool) -> Result<u64> {
+    version_of(&client.get_pull_request(pr_ref, debug)?)
+}
+
+/// Runs `action` with the current version of the pull request, retrying once
+/// with a freshly fetched version if the pull request was modified in between.
+/// Only for changes that don't depend on what the user saw of the pull
+/// request, like declining it; anything else should send the version it
+/// showed and let a conflict through.
+fn with_current_version<T, F>(
+    client: &Bitbucket,
+    pr_ref: &PullRequestRef,
+    debug: bool,
+    action: F,
+) -> Result<T>
+where
+    F: Fn(u64) -> Result<T>,
+{
+    match action(current_version(client, pr_ref, debug)?) {
+        Err(e) => {
+            let conflict = match *e.kind() {
+                ErrorKind::VersionConflict(_) => true,
+                _ => false,
+            };
+            if conflict {
+                action(current_version(client, pr_ref, debug)?)
+            } else {
+                Err(e)
+            }
+        }
+        res => res,
+    }
+}
+
+fn parse_limit(subcmd: &ArgMatches) -> Result<Option<usize>> {
+    match subcmd.value_of("limit") {
+        Some(limit) => limit.parse::<usize>().map(Some).map_err(|_| {
+            ErrorKind::InvalidArgument("limit".to_string(), limit.to_string()).into()
+        }),
+        None => Ok(None),
+    }
+}
+
+fn view(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let pr_ref = resolve_pull_request(config, client, matches.value_of("id"), debug)?;
+    let pull_request = client.get_pull_request(&pr_ref, debug)?;
+    pull_request.print_tty(true);
+    Ok(())
+}
+
+fn review(
+    config: &Config,
+    client: &Bitbucket,
+    matches: &ArgMatches,
+    command: &str,
+    debug: bool,
+) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches(command)
+        .ok_or::<Error>(ErrorKind::MissingSubcommand(command.to_string()).into())?;
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+    let participant = match command {
+        "approve" => client.approve(&pr_ref, debug)?,
+        "unapprove" => client.unapprove(&pr_ref, debug)?,
+        "needs-work" => client.needs_work(&pr_ref, debug)?,
+        _ => unreachable!(),
+    };
+
+    if let Some(participant) = participant {
+        println!(
+            "Set status of pull request {} to {}",
+            pr_ref.id,
+            participant.status()
+        );
+    }
+
+    Ok(())
+}
+
+fn merge(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("merge")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("merge".to_string()).into())?;
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+
+    // the merge has to be of the version the checks below were made for
+    let version = current_version(client, &pr_ref, debug)?;
+    let status = client.merge_status(&pr_ref, debug)?;
+    status.print_tty(true);
+
+    if !status.can_merge() {
+        // any vetoes are in the table above, conflicts come with none
+        if status.is_conflicted() {
+            return Err(ErrorKind::MergeConflicted.into());
+        }
+        let vetoes = status.vetoes();
+        if vetoes.is_empty() {
+            return Err(ErrorKind::MergeVetoed("the server gave no reason".to_string()).into());
+        }
+        return Err(ErrorKind::MergeVetoed(vetoes.join("\n")).into());
+    }
+
+    if subcmd.is_present("check") {
+        return Ok(());
+    }
+
+    let request = MergeRequest::new(subcmd.value_of("message"), subcmd.value_of("strategy"));
+    if client.merge(&pr_ref, version, &request, debug)?.is_some() {
+        println!("Merged pull request {}", pr_ref.id);
+    }
+
+    Ok(())
+}
+
+fn decline(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("decline")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("decline".to_string()).into())?;
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+    let comment = subcmd.value_of("comment");
+    let declined = with_current_version(client, &pr_ref, debug, |version| {
+        client.decline(&pr_ref, version, comment, debug)
+    })?;
+
+    if declined.is_some() {
+        println!("Declined pull request {}", pr_ref.id);
+    }
+    Ok(())
+}
+
+fn reopen(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("reopen")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("reopen".to_string()).into())?;
+
+    // only declined pull requests can be reopened
+    let pr_ref = resolve_pull_request_in_state(
+        config,
+        client,
+        subcmd.value_of("pull_request"),
+        "DECLINED",
+        debug,
+    )?;
+    let reopened = with_current_version(client, &pr_ref, debug, |version| {
+        client.reopen(&pr_ref, version, debug)
+    })?;
+
+    if reopened.is_some() {
+        println!("Reopened pull request {}", pr_ref.id);
+    }
+    Ok(())
+}
+
+fn delete(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("delete")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("delete".to_string()).into())?;
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+
+    // only delete the version that was confirmed
+    let pull_request = client.get_pull_request(&pr_ref, debug)?;
+    let version = version_of(&pull_request)?;
+
+    if !subcmd.is_present("yes") {
+        let answer = util::prompt(&format!(
+            "Really delete pull request {} \"{}\" in {}/{}? This cannot be undone [y/N]: ",
+            pr_ref.id,
+            pull_request.current_title(),
+            pr_ref.project,
+            pr_ref.slug
+        ))?;
+        if answer.to_lowercase() != "y" && answer.to_lowercase() != "yes" {
+            println!("Aborted");
+            return Ok(());
+        }
+    }
+
+    if client.delete_pull_request(&pr_ref, version, debug)? {
+        println!("Deleted pull request {}", pr_ref.id);
+    }
+    Ok(())
+}
+
+/// Computes reviewers from the -r, -g and -a arguments. `base` is used as the
+/// starting set when neither -r nor -g were specified, falling back to the
+/// default group.
+fn compute_reviewers(
+    config: &Config,
+    subcmd: &ArgMatches,
+    base: Option<&HashSet<String>>,
+) -> Result<HashSet<String>> {
+    let mut reviewers = HashSet::new();
+
+    if let Some(reviewer_list) = subcmd.values_of("reviewer") {
+        for reviewer in reviewer_list {
+            reviewers.insert(reviewer.to_string());
+        }
+    } else {
+        if let Some(groups) = subcmd.values_of("group") {
+            for group in groups {
+                reviewers = &reviewers | config.get_group(group)?;
+            }
+        } else {
+            match base {
+                Some(base) => reviewers = &reviewers | base,
+                None => reviewers = &reviewers | config.get_group("default")?,
+            }
+        }
+
+        if let Some(appended) = subcmd.values_of("append") {
+            for append in appended {
+                reviewers.insert(append.to_string());
+            }
+        }
+    }
+
+    Ok(reviewers)
+}
+
+fn edit(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("edit")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("edit".to_string()).into())?;
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+    let mut pull_request = client.get_pull_request(&pr_ref, debug)?;
+
+    if let Some(title) = subcmd.value_of("title") {
+        pull_request.title(title);
+    }
+
+    if let Some(description) = subcmd.value_of("description") {
+        pull_request.description(description);
+    } else if subcmd.is_present("long_description") {
+        let description = Prompt::new()
+            .initial_content(pull_request.current_description())
+            .execute()?
+            .trim()
+            .to_string();
+        pull_request.description(&description);
+    }
+
+    if let Some(branch) = subcmd.value_of("branch") {
+        pull_request.retarget(branch);
+    }
+
+    let existing = pull_request.reviewer_names();
+    let reviewers = compute_reviewers(config, subcmd, Some(&existing))?;
+    if reviewers != existing {
+        println!("computed reviewers: {:?}", reviewers);
+        pull_request.clear_reviewers().reviewers(reviewers.iter());
+    }
+
+    if client.update_pull_request(&pr_ref, &pull_request, debug)?.is_some() {
+        println!("Updated pull request {}", pr_ref.id);
+    }
+
+    Ok(())
+}
+
+fn is_pull_request_id(value: &str) -> bool {
+    value.parse::<u64>().is_ok() || value.contains("://")
+}
+
+fn comment(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("comment")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("comment".to_string()).into())?;
+
+    // both positionals are optional, so `bb comment 'text'` is a comment on
+    // the pull request for the current branch
+    let (id, text) = match (subcmd.value_of("pull_request"), subcmd.value_of("text")) {
+        (Some(first), None) if !is_pull_request_id(first) => (None, Some(first)),
+        (id, text) => (id, text),
+    };
+
+    let text = match text {
+        Some("-") => {
+            let mut content = String::new();
+            io::stdin().read_to_string(&mut content)?;
+            content
+        }
+        Some(text) => text.to_string(),
+        None => Prompt::new().execute()?,
+    };
+    let text = text.trim();
+
+    if text.is_empty() {
+        println!("Empty comment, aborting");
+        return Ok(());
+    }
+
+    let pr_ref = resolve_pull_request(config, client, id, debug)?;
+
+    let mut comment = Comment::new(text);
+
+    if let Some(parent) = subcmd.value_of("reply_to") {
+        let parent = parent
+            .parse::<u64>()
+            .map_err(|_| Error::from(ErrorKind::InvalidCommentId(parent.to_string())))?;
+        comment.reply_to(parent);
+    }
+
+    if let Some(path) = subcmd.value_of("file") {
+        let line = match subcmd.value_of("line") {
+            Some(line) => Some(line.parse::<u64>()
+                .map_err(|_| Error::from(ErrorKind::InvalidLineNumber(line.to_string())))?),
+            None => None,
+        };
+        let line_type = subcmd.value_of("line_type").unwrap_or("added");
+        comment.anchor(Anchor::new(path, line, line_type));
+    }
+
+    let created = match client.add_comment(&pr_ref, &comment, debug)? {
+        Some(created) => created,
+        None => return Ok(()),
+    };
+
+    match created.id() {
+        Some(id) => println!("Added comment {} to pull request {}", id, pr_ref.id),
+        None => println!("Added comment to pull request {}", pr_ref.id),
+    }
+
+    Ok(())
+}
+
+fn activity(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("activity")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("activity".to_string()).into())?;
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+    let timeline = Timeline::new(client.activities(&pr_ref, parse_limit(subcmd)?, debug)?);
+
+    if timeline.is_empty() {
+        println!("No activity on pull request {}", pr_ref.id);
+    } else {
+        timeline.print_tty(true);
+    }
+
+    Ok(())
+}
+
+fn checkout(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("checkout")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("checkout".to_string()).into())?;
+
+    if git::is_dirty()? {
+        return Err(ErrorKind::DirtyWorkingTree.into());
+    }
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+    let pull_request = client.get_pull_request(&pr_ref, debug)?;
+    let source = pull_request
+        .source()
+        .ok_or::<Error>(ErrorKind::InvalidPullRequest("Missing fromRef".to_string()).into())?;
+
+    let project = &source.repository.project.key;
+    let slug = &source.repository.slug;
+    let branch = source.branch();
+    let local_branch = subcmd.value_of("branch").unwrap_or(&branch).to_string();
+
+    if git::branch_exists(&local_branch)? {
+        return Err(ErrorKind::LocalBranchExists(local_branch).into());
+    }
+
+    let remote = match git::find_remote(project, slug)? {
+        Some(remote) => remote,
+        None => {
+            // match whatever protocol origin uses, since that's what the
+            // user's credentials are set up for
+            let ssh = match git::remote_url("origin")? {
+                Some(url) => !url.starts_with("http"),
+                None => true,
+            };
+            let url = source.repository.clone_url(ssh).ok_or::<Error>(
+                ErrorKind::MissingCloneUrl(format!("{}/{}", project, slug)).into(),
+            )?;
+
+            let mut name = project.trim_left_matches('~').to_lowercase();
+            if git::remote_url(&name)?.is_some() {
+                name = format!("{}-{}", name, slug);
+            }
+
+            println!("Adding remote {} for {}", name, url);
+            git::add_remote(&name, &url)?;
+            name
+        }
+    };
+
+    println!("Fetching {} from {}", branch, remote);
+    git::fetch(
+        &remote,
+        &format!("+refs/heads/{}:refs/remotes/{}/{}", branch, remote, branch),
+    )?;
+
+    git::checkout_tracking_branch(&local_branch, &format!("{}/{}", remote, branch))?;
+
+    println!(
+        "Checked out pull request {} as {} tracking {}/{}",
+        pr_ref.id, local_branch, remote, branch
+    );
+
+    Ok(())
+}
+
+fn diff(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("diff")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("diff".to_string()).into())?;
+
+    let context = match subcmd.value_of("context") {
+        Some(context) => Some(context
+            .parse::<u32>()
+            .map_err(|_| {
+                Error::from(ErrorKind::InvalidArgument("context".to_string(), context.to_string()))
+            })?),
+        None => None,
+    };
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+    let mut diffs = client.diff(&pr_ref, context, debug)?;
+
+    if let Some(patterns) = subcmd.values_of("path") {
+        let patterns: Vec<&str> = patterns.collect();
+        diffs.retain(|path| patterns.iter().any(|p| util::glob_match(p, path)));
+    }
+
+    let colorize = !subcmd.is_present("no_color");
+    if diffs.is_empty() {
+        println!("No changes");
+    } else if subcmd.is_present("stat") {
+        diffs.print_stat(colorize);
+    } else {
+        diffs.print(colorize);
+    }
+
+    Ok(())
+}
+
+fn files(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches("files")
+        .ok_or::<Error>(ErrorKind::MissingSubcommand("files".to_string()).into())?;
+
+    let pr_ref = resolve_pull_request(config, client, subcmd.value_of("pull_request"), debug)?;
+    let patterns: Vec<&str> = subcmd
+        .values_of("path")
+        .map(|patterns| patterns.collect())
+        .unwrap_or_default();
+    let changes = client.changes(&pr_ref, &patterns, parse_limit(subcmd)?, debug)?;
+
+    let options = output_options(config, matches)?;
+    if changes.is_empty() && options.format == OutputFormat::Table {
+        println!("No changed files");
+    } else {
+        output::print(&changes, &options, true)?;
+    }
+
     Ok(())
 }
 
@@ -144,112 +808,319 @@ fn pr(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) ->
         .subcommand_matches("pr")
         .ok_or::<Error>(ErrorKind::MissingSubcommand("pr".to_string()).into())?;
 
-    let dry = subcmd.is_present("dry_run");
+    if let Some(view_matches) = subcmd.subcommand_matches("view") {
+        return view(config, client, view_matches, debug);
+    }
+
+    let output = output_format(matches)?;
+    let verbose = output == OutputFormat::Table;
+
+    let project = util::current_project(config)?;
+
+    let branch = git::current_branch()?;
+
+    if config.target_branch_checking {
+        let full_branch = git::current_full_branch()?;
+        if client.branch_exists(&project, &full_branch, debug)? {
+            return Err(ErrorKind::TargetBranchExists(full_branch).into());
+        }
+    }
 
-    let project = config.get_project(&util::get_project_name()?)?;
+    // only pushed right before creating the pull request, so nothing gets
+    // pushed for a pull request that isn't opened in the end
+    let push = subcmd.is_present("push") || config.push;
+    let push_to = check_pushed(client, &project, &branch, push, verbose, debug)?;
+
+    let interactive = subcmd.is_present("interactive");
+    let (mut target_branch, mut reason) =
+        choose_target_branch(config, client, subcmd, &project, debug)?;
+    // asked before the commits, description and template are built from it
+    if interactive {
+        let picked = wizard::ask_target_branch(client, &project, &target_branch, debug)?;
+        if picked != target_branch {
+            target_branch = picked;
+            reason = "picked interactively".to_string();
+        }
+    }
+    let commits = branch_commits(&project, &target_branch)?;
 
-    let commit_summary = git::commit_summary()?;
     let title = match subcmd.value_of("title") {
-        Some(title) => title,
+        Some(title) => title.to_string(),
         None => {
-            println!(
-                "No title specified, using commit summary: \"{}\"",
-                commit_summary
-            );
-            &commit_summary
+            let title = description::default_title(&commits, &branch);
+            if verbose {
+                println!("No title specified, using: \"{}\"", title);
+            }
+            title
         }
     };
 
-    let commit_message = git::commit_message()?;
+    let keys = {
+        let mut texts = vec![branch.as_str()];
+        texts.extend(commits.iter().map(|c| c.message.as_str()));
+        config.issues.find(&texts)
+    };
+    let title = config.issues.title(&title, &keys);
+
+    // the repo's template wins over the configured mode, but not over one
+    // asked for on the command line
+    let (mode, template) = match subcmd.value_of("description_mode") {
+        Some(mode) => (DescriptionMode::from_name(mode)?, None),
+        None => (config.description_mode, util::description_template(&project)?),
+    };
+    // comments are only stripped from text that started out as the template,
+    // not from a description given with -d
+    let from_template = template.is_some() && !subcmd.is_present("description");
+    let generated = match template {
+        Some(ref template) => description::expand_template(
+            template,
+            &description::TemplateValues {
+                commits: &commits,
+                branch: &branch,
+                target: &target_branch,
+                title: &title,
+                keys: &keys,
+            },
+        ),
+        None => description::describe(&commits, mode),
+    };
+
     let mut description = subcmd
         .value_of("description")
-        .unwrap_or(&commit_message)
+        .unwrap_or(&generated)
         .to_string();
     if subcmd.is_present("long_description") {
         description = Prompt::new()
-            .initial_content(&commit_message)
+            .initial_content(&generated)
             .execute()?
             .trim()
             .to_string();
     }
 
-    let branch = git::current_branch()?;
+    let reviewers = compute_reviewers(config, subcmd, None)?;
 
-    if config.target_branch_checking {
-        let full_branch = git::current_full_branch()?;
-        if client.branch_exists(project, &full_branch, debug)? {
-            return Err(ErrorKind::TargetBranchExists(full_branch).into());
+    if verbose {
+        println!("target branch: {} ({})", target_branch, reason);
+        if !keys.is_empty() {
+            println!("issue keys: {}", keys.join(", "));
         }
+        println!("computed reviewers: {:?}", reviewers);
     }
 
-    let target_branch = subcmd.value_of("branch").unwrap_or(&project.target_branch);
-    let mut reviewers = HashSet::new();
+    let draft = wizard::Draft {
+        title: title,
+        description: description,
+        reviewers: reviewers,
+    };
+    let draft = if interactive {
+        wizard::run(config, client, draft, debug)?
+    } else {
+        draft
+    };
 
-    if let Some(reviewer_list) = subcmd.values_of("reviewer") {
-        for reviewer in reviewer_list {
-            reviewers.insert(reviewer.to_string());
+    let mut description = draft.description;
+    if from_template {
+        description = description::strip_comments(&description);
+    }
+    let description = config.issues.link(&description, &keys);
+
+    let checked = reviewers::check(client, &draft.reviewers, debug)?;
+    if !checked.invalid.is_empty() {
+        if !subcmd.is_present("skip_invalid_reviewers") {
+            return Err(ErrorKind::InvalidReviewers(reviewers::describe(&checked.invalid)).into());
         }
-    } else {
-        if let Some(groups) = subcmd.values_of("group") {
-            for group in groups {
-                reviewers = &reviewers | config.get_group(group)?;
+        if verbose {
+            for reviewer in &checked.invalid {
+                println!("Leaving out reviewer {}", reviewer);
             }
-        } else {
-            reviewers = &reviewers | config.get_group("default")?;
         }
+    }
+    let reviewers = checked.valid;
 
-        if let Some(appended) = subcmd.values_of("append") {
-            for append in appended {
-                reviewers.insert(append.to_string());
+    let mut pull_request = PullRequest::new(&draft.title);
+    pull_request
+        .from_ref(&branch, &project.source_slug, &project.source_project)
+        .to_ref(&target_branch, &project.target_slug, &project.target_project)
+        .description(&description)
+        .reviewers(reviewers.iter());
+
+    if interactive && !wizard::confirm_creation(&pull_request, true)? {
+        println!("Aborted");
+        return Ok(());
+    }
+
+    if let Some(remote) = push_to {
+        if client.is_dry_run() {
+            eprintln!("Dry run: would push {} to {}", branch, remote);
+        } else {
+            if verbose {
+                println!("Pushing {} to {}...", branch, remote);
             }
+            git::push(&remote, &branch)?;
         }
     }
 
-    println!("computed reviewers: {:?}", reviewers);
+    let created = match client.create_pull_request(&pull_request, debug)? {
+        Some(created) => created,
+        None => return Ok(()),
+    };
 
-    let url = client.create_pull_request(
-        &PullRequest::new(title)
-            .from_ref(&branch, &project.source_slug, &project.source_project)
-            .to_ref(target_branch, &project.target_slug, &project.target_project)
-            .description(&description)
-            .reviewers(reviewers.iter()),
-        dry,
-        debug,
-    )?;
+    let url = client::get_self_url(&created)?;
 
-    println!("Created pull request: {}", url.as_str());
+    if verbose {
+        println!("Created pull request: {}", url.as_str());
+    } else {
+        let options = output::Options::new(output);
+        output::print(&PullRequestList::new(vec![created]), &options, true)?;
+    }
 
     if subcmd.is_present("open") || config.open_in_browser {
-        println!("Opening in browser...");
+        if verbose {
+            println!("Opening in browser...");
+        }
         util::open_in_browser(config, &url)?;
     }
 
     Ok(())
 }
 
-fn user(client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
+/// The commits a pull request from HEAD would contain, falling back to just
+/// HEAD when the target branch hasn't been fetched
+fn branch_commits(project: &Project, target_branch: &str) -> Result<Vec<git::CommitMessage>> {
+    if let Some(remote) = git::find_remote(&project.target_project, &project.target_slug)? {
+        let base = format!("refs/remotes/{}/{}", remote, target_branch);
+        if let Some(commits) = git::commits_since(&base)? {
+            if !commits.is_empty() {
+                return Ok(commits);
+            }
+        }
+    }
+
+    Ok(vec![git::head_commit()?])
+}
+
+/// Checks whether the server has every commit on the branch. Returns the
+/// remote to push to if it doesn't but pushing is allowed.
+fn check_pushed(
+    client: &Bitbucket,
+    project: &Project,
+    branch: &str,
+    push: bool,
+    verbose: bool,
+    debug: bool,
+) -> Result<Option<String>> {
+    let remote = match git::find_remote(&project.source_project, &project.source_slug)? {
+        Some(remote) => remote,
+        None => {
+            // without a remote for the source repo there's nothing to compare
+            if verbose {
+                println!(
+                    "No git remote found for {}/{}, not checking whether {} is pushed",
+                    project.source_project, project.source_slug, branch
+                );
+            }
+            return Ok(None);
+        }
+    };
+
+    let server_head =
+        client.branch_head(&project.source_project, &project.source_slug, branch, debug)?;
+    let state = git::push_state(&remote, branch, server_head.as_ref().map(|h| h.as_str()))?;
+    match state {
+        PushState::UpToDate => Ok(None),
+        PushState::Missing if !push => {
+            Err(ErrorKind::BranchNotPushed(branch.to_string(), remote).into())
+        }
+        PushState::Ahead(commits) if !push => {
+            Err(ErrorKind::BranchAhead(branch.to_string(), remote, commits).into())
+        }
+        _ => Ok(Some(remote)),
+    }
+}
+
+/// Picks the branch a new pull request targets, along with how it was chosen
+fn choose_target_branch(
+    config: &Config,
+    client: &Bitbucket,
+    subcmd: &ArgMatches,
+    project: &Project,
+    debug: bool,
+) -> Result<(String, String)> {
+    if let Some(branch) = subcmd.value_of("branch") {
+        return Ok((branch.to_string(), "from --branch".to_string()));
+    }
+
+    if let Some(ref branch) = project.target_branch {
+        return Ok((branch.clone(), "from the config file".to_string()));
+    }
+
+    if subcmd.is_present("infer_branch") || config.infer_target_branch {
+        if let Some(remote) = git::find_remote(&project.target_project, &project.target_slug)? {
+            if let Some(branch) = git::closest_remote_branch(&remote)? {
+                let reason = format!("closest merge-base with HEAD on {}", remote);
+                return Ok((branch, reason));
+            }
+        }
+    }
+
+    let branch = client.default_branch(&project.target_project, &project.target_slug, debug)?;
+    Ok((branch, "default branch of the target repo".to_string()))
+}
+
+fn user(config: &Config, client: &Bitbucket, matches: &ArgMatches, debug: bool) -> Result<()> {
     let subcmd = matches
         .subcommand_matches("user")
         .ok_or::<Error>(ErrorKind::MissingSubcommand("user".to_string()).into())?;
     let filter = subcmd.value_of("filter").unwrap(); // This is safe since it's required
 
-    let result = client.user(filter, debug)?;
+    let result = client.user(filter, parse_limit(subcmd)?, debug)?;
 
-    if result.is_empty() {
+    let options = output_options(config, matches)?;
+    if result.is_empty() && options.format == OutputFormat::Table {
         println!("No users found matching the filter: {}", filter);
     } else {
-        result.print_tty(true);
+        output::print(&result, &options, true)?;
     }
 
     Ok(())
 }
 
-fn list(client: &Bitbucket, debug: bool, role: &str) -> Result<()> {
-    let result = client.list_pull_requests(debug, role)?;
-    if result.is_empty() {
-        println!("No open pull requests where your role is {}", role);
+fn list(
+    config: &Config,
+    client: &Bitbucket,
+    matches: &ArgMatches,
+    command: &str,
+    role: Option<&str>,
+    debug: bool,
+) -> Result<()> {
+    let subcmd = matches
+        .subcommand_matches(command)
+        .ok_or::<Error>(ErrorKind::MissingSubcommand(command.to_string()).into())?;
+
+    // these are all validated by clap
+    let filter = PullRequestFilter {
+        role: role.map(|r| r.to_string()),
+        state: subcmd.value_of("state").unwrap_or("open").to_uppercase(),
+        participant_status: subcmd
+            .value_of("participant_status")
+            .map(|s| s.to_uppercase()),
+        order: subcmd.value_of("order").map(|o| o.to_uppercase()),
+        source: None,
+        limit: parse_limit(subcmd)?,
+    };
+
+    let result = if subcmd.is_present("repo") {
+        let project = util::current_project(config)?;
+        client.repo_pull_requests(&project.target_project, &project.target_slug, &filter, debug)?
+    } else {
+        client.list_pull_requests(&filter, debug)?
+    };
+
+    let options = output_options(config, matches)?;
+    if result.is_empty() && options.format == OutputFormat::Table {
+        println!("No pull requests found");
     } else {
-        result.print_tty(true);
+        output::print(&result, &options, true)?;
     }
     Ok(())
 }
@@ -279,20 +1150,53 @@ fn main() {
         }
     }
 
-    let config = Config::from_file(&config_path).unwrap_or_exit("Invalid config file");
-    let client = client::Bitbucket::new(config.auth.clone(), config.server.clone())
+    let profile = global_value(&matches, "profile")
+        .map(|p| p.to_string())
+        .or_else(|| env::var("BB_PROFILE").ok());
+    let config = Config::from_file(&config_path, profile.as_ref().map(|p| p.as_str()))
+        .unwrap_or_else(|why| exit(&format!("Invalid config file: {}", why)));
+
+    // this only needs the config, so don't bother looking up credentials
+    if matches.subcommand_name() == Some("groups") {
+        match groups(&config, &matches) {
+            Err(why) => exit(&format!("{}", why)),
+            Ok(_) => return,
+        }
+    }
+
+    let auth = config
+        .auth
+        .resolve(&config.server)
+        .unwrap_or_else(|why| exit(&format!("{}", why)));
+    let dry_run = global_flag(&matches, "dry_run");
+    let client = client::Bitbucket::new(&auth, config.server.clone(), dry_run)
         .unwrap_or_exit("Could not create client");
 
     let debug = matches.is_present("debug");
+    if debug {
+        println!("using server profile: {}", config.profile);
+    }
 
     let res = match matches.subcommand_name() {
         Some("branch-exists") => branch_exists_on_target(&config, &client, debug),
-        Some("groups") => groups(&config),
         Some("pr") => pr(&config, &client, &matches, debug),
-        Some("user") => user(&client, &matches, debug),
-        Some("list") => list(&client, debug, "ALL"),
-        Some("open") => list(&client, debug, "AUTHOR"),
-        Some("reviewing") => list(&client, debug, "REVIEWER"),
+        Some("user") => user(&config, &client, &matches, debug),
+        Some("list") => list(&config, &client, &matches, "list", None, debug),
+        Some("open") => list(&config, &client, &matches, "open", Some("AUTHOR"), debug),
+        Some("reviewing") => list(&config, &client, &matches, "reviewing", Some("REVIEWER"), debug),
+        Some("merge") => merge(&config, &client, &matches, debug),
+        Some("decline") => decline(&config, &client, &matches, debug),
+        Some("reopen") => reopen(&config, &client, &matches, debug),
+        Some("delete") => delete(&config, &client, &matches, debug),
+        Some("edit") => edit(&config, &client, &matches, debug),
+        Some("comment") => comment(&config, &client, &matches, debug),
+        Some("activity") => activity(&config, &client, &matches, debug),
+        Some("checkout") => checkout(&config, &client, &matches, debug),
+        Some("diff") => diff(&config, &client, &matches, debug),
+        Some("files") => files(&config, &client, &matches, debug),
+        Some(command @ "approve") | Some(command @ "unapprove") | Some(command @ "needs-work") => {
+            review(&config, &client, &matches, command, debug)
+        }
         _ => unreachable!(),
     };
 
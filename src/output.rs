@@ -0,0 +1,456 @@
+use std::fmt::Write;
+
+use prettytable::Table;
+use prettytable::row::Row;
+use prettytable::cell::Cell;
+use prettytable::format;
+use serde_json;
+use serde_json::Value;
+use yaml_rust::{Yaml, YamlEmitter};
+use yaml_rust::yaml::Hash;
+use term_size;
+
+use error::{Error, ErrorKind, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Table,
+    Json,
+    Yaml,
+    Csv,
+    Tsv,
+}
+
+impl OutputFormat {
+    pub fn from_name(name: &str) -> Result<OutputFormat> {
+        match name {
+            "table" => Ok(OutputFormat::Table),
+            "json" => Ok(OutputFormat::Json),
+            "yaml" => Ok(OutputFormat::Yaml),
+            "csv" => Ok(OutputFormat::Csv),
+            "tsv" => Ok(OutputFormat::Tsv),
+            _ => Err(ErrorKind::InvalidArgument("output".to_string(), name.to_string()).into()),
+        }
+    }
+}
+
+/// How a listing should be printed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub format: OutputFormat,
+    /// Only show these fields, in this order
+    pub columns: Option<Vec<String>>,
+    /// Print each record with a `{{field}}` template instead of `format`
+    pub template: Option<String>,
+}
+
+impl Options {
+    pub fn new(format: OutputFormat) -> Options {
+        Options {
+            format: format,
+            columns: None,
+            template: None,
+        }
+    }
+}
+
+/// Anything that can be shown as a list of records with the same fields.
+/// Field names are part of the machine-readable output, so they should not
+/// change once released.
+pub trait Tabular {
+    fn fields(&self) -> Vec<&'static str>;
+
+    /// The fields shown in a table when no columns were asked for. The
+    /// machine-readable formats always default to every field.
+    fn default_fields(&self) -> Vec<&'static str> {
+        self.fields()
+    }
+
+    /// One row per record, with values in the same order as `fields`
+    fn records(&self) -> Vec<Vec<Value>>;
+}
+
+/// Splits a comma separated list of column names
+pub fn parse_columns(columns: &str) -> Vec<String> {
+    columns
+        .split(',')
+        .map(|c| c.trim().to_string())
+        .filter(|c| !c.is_empty())
+        .collect()
+}
+
+/// Converts a missing value to null
+pub fn optional<T: Into<Value>>(value: Option<T>) -> Value {
+    match value {
+        Some(value) => value.into(),
+        None => Value::Null,
+    }
+}
+
+pub fn titled_format() -> format::TableFormat {
+    format::FormatBuilder::new()
+        .padding(1, 1)
+        .separator(
+            format::LinePosition::Title,
+            format::LineSeparator::new('-', '-', '-', '-'),
+        )
+        .build()
+}
+
+pub fn print<T: Tabular>(data: &T, options: &Options, force_colorize: bool) -> Result<()> {
+    if options.template.is_none() && options.format == OutputFormat::Table {
+        let (fields, records) = select(data, options)?;
+        let width = term_size::dimensions_stdout().map(|(w, _)| w);
+        table(&fields, &records, width).print_tty(force_colorize);
+    } else {
+        print!("{}", render(data, options)?);
+    }
+    Ok(())
+}
+
+/// Picks the requested columns out of every record
+fn select<T: Tabular>(data: &T, options: &Options) -> Result<(Vec<&'static str>, Vec<Vec<Value>>)> {
+    let all = data.fields();
+    let fields = match options.columns {
+        Some(ref columns) => {
+            let mut fields = Vec::new();
+            for column in columns {
+                match all.iter().find(|f| *f == column) {
+                    Some(field) => fields.push(*field),
+                    None => {
+                        return Err(ErrorKind::InvalidArgument(
+                            "columns".to_string(),
+                            format!("{} (available: {})", column, all.join(", ")),
+                        ).into())
+                    }
+                }
+            }
+            fields
+        }
+        None if options.format == OutputFormat::Table && options.template.is_none() => {
+            data.default_fields()
+        }
+        None => all.clone(),
+    };
+
+    let indices: Vec<usize> = fields
+        .iter()
+        .map(|f| all.iter().position(|a| a == f).unwrap())
+        .collect();
+    let records = data.records()
+        .into_iter()
+        .map(|record| indices.iter().map(|i| record[*i].clone()).collect())
+        .collect();
+
+    Ok((fields, records))
+}
+
+/// Builds a table, shrinking the widest columns until the table fits in
+/// `width` characters
+fn table(fields: &[&str], records: &[Vec<Value>], width: Option<usize>) -> Table {
+    let rows: Vec<Vec<String>> = records
+        .iter()
+        .map(|record| record.iter().map(value_to_string).collect())
+        .collect();
+
+    let mut widths: Vec<usize> = fields.iter().map(|f| f.chars().count()).collect();
+    for row in &rows {
+        for (i, text) in row.iter().enumerate() {
+            let len = text.lines().map(|l| l.chars().count()).max().unwrap_or(0);
+            if len > widths[i] {
+                widths[i] = len;
+            }
+        }
+    }
+    if let Some(width) = width {
+        fit_widths(&mut widths, width);
+    }
+
+    let mut table = Table::new();
+    table.set_format(titled_format());
+    table.set_titles(Row::new(fields.iter().map(|f| Cell::new(f)).collect()));
+    for row in rows {
+        let cells = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(text, width)| Cell::new(&truncate(text, *width)))
+            .collect();
+        table.add_row(Row::new(cells));
+    }
+
+    table
+}
+
+/// Columns are never shrunk below this many characters
+const MIN_COLUMN_WIDTH: usize = 8;
+
+fn fit_widths(widths: &mut Vec<usize>, available: usize) {
+    // every column is padded by one space on each side
+    let padding = widths.len() * 2;
+    loop {
+        let total: usize = widths.iter().sum::<usize>() + padding;
+        if total <= available {
+            return;
+        }
+
+        let (widest, width) = match widths.iter().cloned().enumerate().max_by_key(|&(_, w)| w) {
+            Some(widest) => widest,
+            None => return,
+        };
+        if width <= MIN_COLUMN_WIDTH {
+            return;
+        }
+        widths[widest] = ::std::cmp::max(width.saturating_sub(total - available), MIN_COLUMN_WIDTH);
+    }
+}
+
+fn truncate(text: &str, width: usize) -> String {
+    text.lines()
+        .map(|line| {
+            if line.chars().count() > width {
+                let mut short: String = line.chars().take(width - 1).collect();
+                short.push('…');
+                short
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Renders the data as text without regard for the terminal size
+pub fn render<T: Tabular>(data: &T, options: &Options) -> Result<String> {
+    let (fields, records) = select(data, options)?;
+
+    if let Some(ref template) = options.template {
+        return expand_template(template, &fields, &records);
+    }
+
+    match options.format {
+        OutputFormat::Json => {
+            let objects: Vec<Value> = records
+                .into_iter()
+                .map(|record| {
+                    let mut object = serde_json::Map::new();
+                    for (field, value) in fields.iter().zip(record.into_iter()) {
+                        object.insert(field.to_string(), value);
+                    }
+                    Value::Object(object)
+                })
+                .collect();
+            Ok(format!("{}\n", serde_json::to_string_pretty(&objects)?))
+        }
+        OutputFormat::Yaml => {
+            let documents = records
+                .into_iter()
+                .map(|record| {
+                    let mut hash = Hash::new();
+                    for (field, value) in fields.iter().zip(record.into_iter()) {
+                        hash.insert(Yaml::String(field.to_string()), value_to_yaml(value));
+                    }
+                    Yaml::Hash(hash)
+                })
+                .collect();
+            let mut out = String::new();
+            YamlEmitter::new(&mut out)
+                .dump(&Yaml::Array(documents))
+                .map_err(|e| ErrorKind::OutputError(format!("{:?}", e)))?;
+            Ok(format!("{}\n", out))
+        }
+        OutputFormat::Csv => Ok(delimited(&fields, &records, ',', csv_escape)),
+        OutputFormat::Tsv => Ok(delimited(&fields, &records, '\t', tsv_escape)),
+        OutputFormat::Table => Ok(table(&fields, &records, None).to_string()),
+    }
+}
+
+/// Expands `{{field}}` placeholders once per record. `\t` and `\n` are
+/// unescaped since they're awkward to type in most shells.
+fn expand_template(template: &str, fields: &[&str], records: &[Vec<Value>]) -> Result<String> {
+    let template = template.replace("\\t", "\t").replace("\\n", "\n");
+    let mut out = String::new();
+
+    for record in records {
+        let mut rest = template.as_str();
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let end = rest[start..]
+                .find("}}")
+                .ok_or::<Error>(ErrorKind::InvalidArgument("format".to_string(), template.clone()).into())?;
+            let name = rest[start + 2..start + end].trim();
+            let index = fields.iter().position(|f| *f == name).ok_or::<Error>(
+                ErrorKind::InvalidArgument(
+                    "format".to_string(),
+                    format!("unknown field {} (available: {})", name, fields.join(", ")),
+                ).into(),
+            )?;
+            out.push_str(&value_to_string(&record[index]));
+            rest = &rest[start + end + 2..];
+        }
+        out.push_str(rest);
+        out.push('\n');
+    }
+
+    Ok(out)
+}
+
+fn delimited<F>(fields: &[&str], records: &[Vec<Value>], separator: char, escape: F) -> String
+where
+    F: Fn(&str) -> String,
+{
+    let mut out = String::new();
+    let header: Vec<String> = fields.iter().map(|f| escape(f)).collect();
+    // writing to a String can't fail
+    let _ = writeln!(out, "{}", header.join(&separator.to_string()));
+
+    for record in records {
+        let values: Vec<String> = record
+            .iter()
+            .map(|v| escape(&value_to_string(v)))
+            .collect();
+        let _ = writeln!(out, "{}", values.join(&separator.to_string()));
+    }
+
+    out
+}
+
+fn csv_escape(value: &str) -> String {
+    if value.contains(',') || value.contains('"') || value.contains('\n') {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn tsv_escape(value: &str) -> String {
+    value.replace('\t', " ").replace('\n', " ")
+}
+
+fn value_to_string(value: &Value) -> String {
+    match *value {
+        Value::Null => String::new(),
+        Value::String(ref s) => s.clone(),
+        Value::Array(ref values) => values
+            .iter()
+            .map(value_to_string)
+            .collect::<Vec<String>>()
+            .join(", "),
+        ref other => other.to_string(),
+    }
+}
+
+fn value_to_yaml(value: Value) -> Yaml {
+    match value {
+        Value::Null => Yaml::Null,
+        Value::Bool(b) => Yaml::Boolean(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Yaml::Integer(i),
+            None => Yaml::Real(n.to_string()),
+        },
+        Value::String(s) => Yaml::String(s),
+        Value::Array(values) => Yaml::Array(values.into_iter().map(value_to_yaml).collect()),
+        Value::Object(object) => {
+            let mut hash = Hash::new();
+            for (key, value) in object {
+                hash.insert(Yaml::String(key), value_to_yaml(value));
+            }
+            Yaml::Hash(hash)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct People;
+
+    impl Tabular for People {
+        fn fields(&self) -> Vec<&'static str> {
+            vec!["id", "name", "groups"]
+        }
+
+        fn records(&self) -> Vec<Vec<Value>> {
+            vec![
+                vec![
+                    Value::from(1),
+                    Value::from("Foo, Jr."),
+                    Value::from(vec!["a", "b"]),
+                ],
+                vec![Value::from(2), Value::from("bar\tbaz"), Value::Null],
+            ]
+        }
+    }
+
+    #[test]
+    fn rendering_json() {
+        let out = render(&People, &Options::new(OutputFormat::Json)).unwrap();
+        let parsed: Value = serde_json::from_str(&out).unwrap();
+        assert_eq!(1, parsed[0]["id"]);
+        assert_eq!("Foo, Jr.", parsed[0]["name"]);
+        assert_eq!("b", parsed[0]["groups"][1]);
+        assert!(parsed[1]["groups"].is_null());
+    }
+
+    #[test]
+    fn rendering_csv() {
+        let out = render(&People, &Options::new(OutputFormat::Csv)).unwrap();
+        assert_eq!(
+            "id,name,groups\n1,\"Foo, Jr.\",\"a, b\"\n2,bar\tbaz,\n",
+            out
+        );
+    }
+
+    #[test]
+    fn rendering_tsv() {
+        let out = render(&People, &Options::new(OutputFormat::Tsv)).unwrap();
+        assert_eq!("id\tname\tgroups\n1\tFoo, Jr.\ta, b\n2\tbar baz\t\n", out);
+    }
+
+    #[test]
+    fn rendering_yaml() {
+        let out = render(&People, &Options::new(OutputFormat::Yaml)).unwrap();
+        assert!(out.contains("Foo, Jr."));
+        assert!(out.contains("id: 2"));
+    }
+
+    #[test]
+    fn selecting_columns() {
+        let mut options = Options::new(OutputFormat::Csv);
+        options.columns = Some(parse_columns("name, id"));
+        let out = render(&People, &options).unwrap();
+        assert_eq!("name,id\n\"Foo, Jr.\",1\nbar\tbaz,2\n", out);
+
+        options.columns = Some(parse_columns("id,nope"));
+        assert!(render(&People, &options).is_err());
+    }
+
+    #[test]
+    fn expanding_templates() {
+        let mut options = Options::new(OutputFormat::Table);
+        options.template = Some("{{id}}\\t{{ name }} [{{groups}}]".to_string());
+        let out = render(&People, &options).unwrap();
+        assert_eq!("1\tFoo, Jr. [a, b]\n2\tbar\tbaz []\n", out);
+
+        options.template = Some("{{missing}}".to_string());
+        assert!(render(&People, &options).is_err());
+    }
+
+    #[test]
+    fn fitting_columns_to_width() {
+        let mut widths = vec![3, 60, 20];
+        fit_widths(&mut widths, 60);
+        assert_eq!(vec![3, 31, 20], widths);
+
+        // never shrinks below the minimum, even if it still doesn't fit
+        let mut widths = vec![10, 10];
+        fit_widths(&mut widths, 5);
+        assert_eq!(vec![8, 8], widths);
+    }
+
+    #[test]
+    fn truncating_cells() {
+        assert_eq!("abc", truncate("abc", 8));
+        assert_eq!("abcdefg…", truncate("abcdefghij", 8));
+    }
+}
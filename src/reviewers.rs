@@ -0,0 +1,187 @@
+use std::cmp;
+use std::collections::HashSet;
+use std::fmt;
+
+use bitbucket_data::User;
+use client::Bitbucket;
+use error::Result;
+
+/// How many users a search for a reviewer's name looks through
+const SEARCH_LIMIT: usize = 25;
+/// How many close matches are suggested for an unknown reviewer
+const MAX_SUGGESTIONS: usize = 3;
+/// How much of an unknown name is searched for when the whole name matches
+/// nobody, since the filter only matches substrings
+const PREFIX_LENGTH: usize = 3;
+
+/// Why a reviewer can't be added to a pull request
+#[derive(Debug, PartialEq)]
+pub enum Problem {
+    /// No user has the name, with the closest names that do exist
+    Unknown(Vec<String>),
+    Deactivated,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct InvalidReviewer {
+    pub name: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for InvalidReviewer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.problem {
+            Problem::Unknown(ref matches) if matches.is_empty() => {
+                write!(f, "{}: no such user", self.name)
+            }
+            Problem::Unknown(ref matches) => write!(
+                f,
+                "{}: no such user, did you mean {}?",
+                self.name,
+                matches.join(" or ")
+            ),
+            Problem::Deactivated => write!(f, "{}: deactivated", self.name),
+        }
+    }
+}
+
+/// The outcome of looking the reviewers up on the server
+#[derive(Debug, PartialEq)]
+pub struct Checked {
+    /// Spelled the way the server does, which may differ in case from how
+    /// they were given
+    pub valid: HashSet<String>,
+    /// In name order
+    pub invalid: Vec<InvalidReviewer>,
+}
+
+/// Looks every reviewer up on the server
+pub fn check(client: &Bitbucket, reviewers: &HashSet<String>, debug: bool) -> Result<Checked> {
+    let mut names: Vec<&String> = reviewers.iter().collect();
+    names.sort();
+
+    let mut valid = HashSet::new();
+    let mut invalid = Vec::new();
+    for name in names {
+        let found = client.user(name, Some(SEARCH_LIMIT), debug)?;
+        let problem = match found.find_by_name(name) {
+            Some(user) if user.is_active() => {
+                valid.insert(user.name().to_string());
+                continue;
+            }
+            Some(_) => Problem::Deactivated,
+            None if found.is_empty() && name.chars().count() > PREFIX_LENGTH => {
+                let prefix: String = name.chars().take(PREFIX_LENGTH).collect();
+                let similar = client.user(&prefix, Some(SEARCH_LIMIT), debug)?;
+                Problem::Unknown(suggest(name, similar.users()))
+            }
+            None => Problem::Unknown(suggest(name, found.users())),
+        };
+
+        invalid.push(InvalidReviewer {
+            name: name.clone(),
+            problem: problem,
+        });
+    }
+
+    Ok(Checked {
+        valid: valid,
+        invalid: invalid,
+    })
+}
+
+/// One line per invalid reviewer
+pub fn describe(invalid: &[InvalidReviewer]) -> String {
+    invalid
+        .iter()
+        .map(|reviewer| reviewer.to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// The names of the active users closest to the given name
+fn suggest(name: &str, users: &[User]) -> Vec<String> {
+    let name = name.to_lowercase();
+    let mut candidates: Vec<(usize, &str)> = users
+        .iter()
+        .filter(|user| user.is_active())
+        .map(|user| (edit_distance(&name, &user.name().to_lowercase()), user.name()))
+        .collect();
+    candidates.sort();
+
+    candidates
+        .into_iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+/// The Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..b.len() + 1).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            let insertion = current[j] + 1;
+            let deletion = previous[j + 1] + 1;
+            current.push(cmp::min(substitution, cmp::min(insertion, deletion)));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distances() {
+        assert_eq!(0, edit_distance("jsmith", "jsmith"));
+        assert_eq!(2, edit_distance("jsmtih", "jsmith"));
+        assert_eq!(3, edit_distance("bob", "bobby."));
+        assert_eq!(4, edit_distance("", "nope"));
+    }
+
+    #[test]
+    fn suggesting_close_names() {
+        let users = vec![
+            User::new("jsmythe"),
+            User::new("jsmith"),
+            User::new("jane"),
+            User::new("jsmithers"),
+            User::new("jsmit"),
+        ];
+        assert_eq!(
+            vec!["jsmit".to_string(), "jsmith".to_string(), "jsmythe".to_string()],
+            suggest("JSmtih", &users)
+        );
+        assert!(suggest("jsmith", &[]).is_empty());
+    }
+
+    #[test]
+    fn describing_invalid_reviewers() {
+        let invalid = vec![
+            InvalidReviewer {
+                name: "jsmtih".to_string(),
+                problem: Problem::Unknown(vec!["jsmith".to_string(), "jsmit".to_string()]),
+            },
+            InvalidReviewer {
+                name: "nobody".to_string(),
+                problem: Problem::Unknown(vec![]),
+            },
+            InvalidReviewer {
+                name: "gone".to_string(),
+                problem: Problem::Deactivated,
+            },
+        ];
+        assert_eq!(
+            "jsmtih: no such user, did you mean jsmith or jsmit?\nnobody: no such user\ngone: deactivated",
+            describe(&invalid)
+        );
+    }
+}
@@ -1,13 +1,22 @@
 use std::fs::File;
-use std::io::Read;
+use std::io;
+use std::io::{Read, Write};
 use std::process::Command;
 
 use hyper::Url;
 
-use config::Config;
-use error::Result;
+use config::{Config, Project};
+use error::{ErrorKind, Result};
 use git;
 
+pub fn prompt(label: &str) -> Result<String> {
+    print!("{}", label);
+    io::stdout().flush()?; // need to do this since print! won't flush
+    let mut res = String::new();
+    io::stdin().read_line(&mut res)?;
+    Ok(res.trim().to_string())
+}
+
 pub fn get_project_name() -> Result<String> {
     let repo_name = git::repo_name()?;
 
@@ -24,6 +33,101 @@ pub fn get_project_name() -> Result<String> {
     };
 }
 
+/// The project for the current repo: the configured entry if there is one,
+/// otherwise one detected from the git remotes
+pub fn current_project(config: &Config) -> Result<Project> {
+    let name = get_project_name()?;
+    if let Ok(project) = config.get_project(&name) {
+        return Ok(project.clone());
+    }
+
+    match detect_project(config)? {
+        Some(project) => Ok(project),
+        None => Err(ErrorKind::ProjectNotFound(name).into()),
+    }
+}
+
+fn detect_project(config: &Config) -> Result<Option<Project>> {
+    let server_host = remote_host(&config.server);
+    // remotes on other servers (e.g. a github mirror) aren't useful
+    let on_server = |url: Option<String>| -> Result<Option<(String, String)>> {
+        match url {
+            Some(ref url) if remote_host(url) == server_host => match parse_remote_url(url) {
+                Some(repo) => Ok(Some(repo)),
+                None => Err(ErrorKind::InvalidRemoteUrl(url.to_string()).into()),
+            },
+            _ => Ok(None),
+        }
+    };
+
+    let (source_project, source_slug) = match on_server(git::remote_url("origin")?)? {
+        Some(source) => source,
+        None => return Ok(None),
+    };
+
+    let (target_project, target_slug) = match on_server(git::remote_url(&config.target_remote)?)? {
+        Some(target) => target,
+        None => (source_project.clone(), source_slug.clone()),
+    };
+
+    Ok(Some(Project {
+        source_project: source_project,
+        source_slug: source_slug,
+        target_project: target_project,
+        target_slug: target_slug,
+        target_branch: None,
+        description_template: None,
+    }))
+}
+
+/// Extracts the project key and repo slug from a bitbucket server clone url.
+/// Personal repos have a `~user` project key.
+pub fn parse_remote_url(url: &str) -> Option<(String, String)> {
+    let path = if url.contains("://") {
+        Url::parse(url).ok()?.path().to_string()
+    } else {
+        // scp-like user@host:path
+        url.splitn(2, ':').nth(1)?.to_string()
+    };
+
+    let mut segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+    // http urls are <context path>/scm/KEY/slug
+    if let Some(scm) = segments.iter().position(|s| *s == "scm") {
+        segments = segments.split_off(scm + 1);
+    }
+    if segments.len() != 2 {
+        return None;
+    }
+
+    let slug = segments[1].trim_right_matches(".git");
+    if slug.is_empty() {
+        return None;
+    }
+
+    Some((segments[0].to_uppercase(), slug.to_string()))
+}
+
+/// The contents of the pull request description template for the project,
+/// if there is one
+pub fn description_template(project: &Project) -> Result<Option<String>> {
+    let mut path = git::repo_dir()?;
+    match project.description_template {
+        Some(ref template) => path.push(template),
+        None => {
+            path.push(".bitbucket");
+            path.push("pull_request_template.md");
+            if !path.exists() {
+                return Ok(None);
+            }
+        }
+    }
+
+    // a configured template that's missing is an error
+    let mut content = String::new();
+    File::open(path)?.read_to_string(&mut content)?;
+    Ok(Some(content))
+}
+
 pub fn open_in_browser(config: &Config, url: &Url) -> Result<()> {
     match Command::new(config.browser_command.as_str())
         .arg(url.as_str())
@@ -33,3 +137,136 @@ pub fn open_in_browser(config: &Config, url: &Url) -> Result<()> {
         Ok(_) => Ok(()),
     }
 }
+
+/// The host of a git remote or server url. Remotes may also be written in the
+/// scp-like `user@host:path` form.
+pub fn remote_host(url: &str) -> Option<String> {
+    if url.contains("://") {
+        return Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_lowercase()));
+    }
+
+    let host = url.split(':').next().unwrap_or("");
+    let host = host.rsplit('@').next().unwrap_or(host);
+    if host.is_empty() || host.contains('/') {
+        None
+    } else {
+        Some(host.to_lowercase())
+    }
+}
+
+/// Quotes a value for writing into the config file as a double quoted YAML
+/// string
+pub fn yaml_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Matches a path against a glob pattern where `*` and `?` don't cross
+/// directory boundaries and `**` matches any number of directories. Patterns
+/// without a `/` are matched against the file name only.
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    if !pattern.contains('/') {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        return glob_match_chars(pattern.as_bytes(), name.as_bytes());
+    }
+    glob_match_chars(pattern.as_bytes(), path.as_bytes())
+}
+
+fn glob_match_chars(pattern: &[u8], path: &[u8]) -> bool {
+    if pattern.is_empty() {
+        return path.is_empty();
+    }
+
+    if pattern.starts_with(b"**/") {
+        // `**/` matches whole directories, including none at all, so the rest
+        // of the pattern can only start at the beginning of a path component
+        let rest = &pattern[3..];
+        return (0..path.len() + 1)
+            .filter(|&i| i == 0 || path[i - 1] == b'/')
+            .any(|i| glob_match_chars(rest, &path[i..]));
+    }
+
+    if pattern.starts_with(b"**") {
+        let rest = &pattern[2..];
+        return (0..path.len() + 1).any(|i| glob_match_chars(rest, &path[i..]));
+    }
+
+    match pattern[0] {
+        b'*' => (0..path.len() + 1)
+            .take_while(|&i| i == 0 || path[i - 1] != b'/')
+            .any(|i| glob_match_chars(&pattern[1..], &path[i..])),
+        b'?' => !path.is_empty() && path[0] != b'/' && glob_match_chars(&pattern[1..], &path[1..]),
+        c => !path.is_empty() && path[0] == c && glob_match_chars(&pattern[1..], &path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remote_hosts() {
+        let host = Some("bitbucket.example.com".to_string());
+        assert_eq!(host, remote_host("https://bitbucket.example.com/stash"));
+        assert_eq!(host, remote_host("https://me@Bitbucket.example.com:8443/scm/foo/bar.git"));
+        assert_eq!(host, remote_host("ssh://git@bitbucket.example.com:7999/foo/bar.git"));
+        assert_eq!(host, remote_host("git@bitbucket.example.com:foo/bar.git"));
+        assert_eq!(None, remote_host("/some/local/path"));
+    }
+
+    #[test]
+    fn quoting_yaml() {
+        assert_eq!(r#""pass show bb""#, yaml_quote("pass show bb"));
+        assert_eq!(r#""pass show \"bb\" \\ x\n""#, yaml_quote("pass show \"bb\" \\ x\n"));
+    }
+
+    #[test]
+    fn parsing_remote_urls() {
+        let repo = Some(("KEY".to_string(), "slug".to_string()));
+        assert_eq!(repo, parse_remote_url("ssh://git@host:7999/key/slug.git"));
+        assert_eq!(repo, parse_remote_url("https://host/scm/KEY/slug.git"));
+        assert_eq!(repo, parse_remote_url("https://me@host/stash/scm/key/slug"));
+        assert_eq!(repo, parse_remote_url("git@host:KEY/slug.git"));
+
+        let personal = Some(("~USER".to_string(), "slug".to_string()));
+        assert_eq!(personal, parse_remote_url("https://host/scm/~user/slug.git"));
+        assert_eq!(personal, parse_remote_url("ssh://git@host:7999/~user/slug.git"));
+
+        assert_eq!(None, parse_remote_url("https://host/slug.git"));
+        assert_eq!(None, parse_remote_url("/some/local/path"));
+    }
+
+    #[test]
+    fn glob_matching_file_names() {
+        assert!(glob_match("*.rs", "src/main.rs"));
+        assert!(glob_match("main.?s", "src/main.rs"));
+        assert!(!glob_match("*.rs", "src/app.yml"));
+    }
+
+    #[test]
+    fn glob_matching_paths() {
+        assert!(glob_match("src/*.rs", "src/main.rs"));
+        assert!(!glob_match("src/*.rs", "src/bin/main.rs"));
+        assert!(glob_match("src/**/*.rs", "src/bin/main.rs"));
+        assert!(glob_match("src/**/*.rs", "src/main.rs"));
+        assert!(glob_match("**/main.rs", "src/bin/main.rs"));
+        assert!(glob_match("**/main.rs", "main.rs"));
+        assert!(!glob_match("**/main.rs", "src/bin/notmain.rs"));
+        assert!(!glob_match("src/**/main.rs", "src/notmain.rs"));
+        assert!(!glob_match("src/**/main.rs", "srcx/main.rs"));
+        assert!(glob_match("docs/**", "docs/guide/intro.md"));
+        assert!(!glob_match("docs/**", "src/main.rs"));
+    }
+}
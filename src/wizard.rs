@@ -0,0 +1,271 @@
+use std::collections::HashSet;
+
+use eprompt::Prompt;
+
+use bitbucket_data::PullRequest;
+use client::Bitbucket;
+use config::{Config, Project};
+use error::Result;
+use util::prompt;
+
+/// How many of the target repo's branches are offered
+const BRANCH_CHOICES: usize = 15;
+/// How many users a search offers
+const USER_CHOICES: usize = 10;
+
+/// The parts of a new pull request the wizard asks about. It starts out with
+/// what the flags and config would have produced, so every question can be
+/// answered by just hitting enter. The target branch is asked about
+/// separately, before anything is built from it.
+pub struct Draft {
+    pub title: String,
+    pub description: String,
+    pub reviewers: HashSet<String>,
+}
+
+/// Walks through the title, description and reviewers
+pub fn run(
+    config: &Config,
+    client: &Bitbucket,
+    draft: Draft,
+    debug: bool,
+) -> Result<Draft> {
+    let title = ask_title(&draft.title)?;
+    let description = ask_description(&draft.description)?;
+    let reviewers = ask_reviewers(config, client, draft.reviewers, debug)?;
+
+    Ok(Draft {
+        title: title,
+        description: description,
+        reviewers: reviewers,
+    })
+}
+
+/// Shows the pull request about to be created and asks whether to go ahead
+pub fn confirm_creation(pull_request: &PullRequest, force_colorize: bool) -> Result<bool> {
+    println!("");
+    pull_request.print_preview(force_colorize);
+    println!("");
+    confirm("Create this pull request?", false)
+}
+
+fn ask_title(current: &str) -> Result<String> {
+    let title = prompt(&format!("title [{}]: ", current))?;
+    if title.is_empty() {
+        Ok(current.to_string())
+    } else {
+        Ok(title)
+    }
+}
+
+fn ask_description(current: &str) -> Result<String> {
+    println!("");
+    if current.is_empty() {
+        println!("The description is empty");
+    } else {
+        println!("description:\n{}", current);
+    }
+    println!("");
+
+    if confirm("Edit the description in $EDITOR?", true)? {
+        Ok(Prompt::new()
+            .initial_content(current)
+            .execute()?
+            .trim()
+            .to_string())
+    } else {
+        Ok(current.to_string())
+    }
+}
+
+/// Offers the target repo's branches, keeping the current one on an empty
+/// answer
+pub fn ask_target_branch(
+    client: &Bitbucket,
+    project: &Project,
+    current: &str,
+    debug: bool,
+) -> Result<String> {
+    let branches = client.branches(
+        &project.target_project,
+        &project.target_slug,
+        Some(BRANCH_CHOICES),
+        debug,
+    )?;
+    let names: Vec<String> = branches.iter().map(|b| b.name().to_string()).collect();
+
+    println!("");
+    println!(
+        "Branches of {}/{}, most recently changed first:",
+        project.target_project, project.target_slug
+    );
+    for (i, branch) in branches.iter().enumerate() {
+        let mut notes = Vec::new();
+        if branch.is_default() {
+            notes.push("default");
+        }
+        if branch.name() == current {
+            notes.push("current");
+        }
+        if notes.is_empty() {
+            println!("{:>4}) {}", i + 1, branch.name());
+        } else {
+            println!("{:>4}) {} ({})", i + 1, branch.name(), notes.join(", "));
+        }
+    }
+
+    // branches past the first few can still be typed in by name
+    let answer = prompt(&format!("target branch (number or name) [{}]: ", current))?;
+    Ok(pick(&answer, &names).unwrap_or(current.to_string()))
+}
+
+fn ask_reviewers(
+    config: &Config,
+    client: &Bitbucket,
+    mut reviewers: HashSet<String>,
+    debug: bool,
+) -> Result<HashSet<String>> {
+    println!("");
+    if !reviewers.is_empty() {
+        let current = sorted(&reviewers);
+        println!("Reviewers:");
+        print_choices(&current);
+        for name in ask_many("reviewers to remove (numbers, empty for none): ", &current)? {
+            reviewers.remove(&name);
+        }
+    }
+
+    let groups: Vec<String> = config.groups.keys().cloned().collect();
+    if !groups.is_empty() {
+        println!("Groups:");
+        for (i, group) in groups.iter().enumerate() {
+            let members = sorted(&config.groups[group]);
+            println!("{:>4}) {}: {}", i + 1, group, members.join(", "));
+        }
+        for group in ask_many("groups to add (numbers or names, empty for none): ", &groups)? {
+            reviewers = &reviewers | config.get_group(&group)?;
+        }
+    }
+
+    loop {
+        let filter = prompt("search for a user to add (empty to finish): ")?;
+        if filter.is_empty() {
+            break;
+        }
+
+        let found = client.user(&filter, Some(USER_CHOICES), debug)?;
+        if found.is_empty() {
+            println!("No users match \"{}\"", filter);
+            continue;
+        }
+
+        let names: Vec<String> = found.users().iter().map(|u| u.name().to_string()).collect();
+        for (i, user) in found.users().iter().enumerate() {
+            println!("{:>4}) {} ({})", i + 1, user.name(), user.display_name());
+        }
+        for name in ask_many("users to add (numbers or names, empty for none): ", &names)? {
+            reviewers.insert(name);
+        }
+    }
+
+    Ok(reviewers)
+}
+
+/// Asks until every answer is one of the choices
+fn ask_many(label: &str, choices: &[String]) -> Result<Vec<String>> {
+    loop {
+        let answer = prompt(label)?;
+        match pick_many(&answer, choices) {
+            Some(picked) => return Ok(picked),
+            None => println!("Pick from the numbers or names listed above"),
+        }
+    }
+}
+
+fn confirm(question: &str, default: bool) -> Result<bool> {
+    let hint = if default { "Y/n" } else { "y/N" };
+    let answer = prompt(&format!("{} [{}]: ", question, hint))?.to_lowercase();
+    Ok(match answer.as_str() {
+        "y" | "yes" => true,
+        "n" | "no" => false,
+        _ => default,
+    })
+}
+
+fn print_choices(choices: &[String]) {
+    for (i, choice) in choices.iter().enumerate() {
+        println!("{:>4}) {}", i + 1, choice);
+    }
+}
+
+fn sorted(names: &HashSet<String>) -> Vec<String> {
+    let mut names: Vec<String> = names.iter().cloned().collect();
+    names.sort();
+    names
+}
+
+/// The choice numbered by the answer, or the answer itself. Nothing for an
+/// empty answer.
+fn pick(answer: &str, choices: &[String]) -> Option<String> {
+    if answer.is_empty() {
+        return None;
+    }
+
+    match answer.parse::<usize>() {
+        Ok(n) if n >= 1 && n <= choices.len() => Some(choices[n - 1].clone()),
+        _ => Some(answer.to_string()),
+    }
+}
+
+/// The choices picked by a comma or space separated list of numbers or
+/// names, or nothing if any of them isn't one of the choices
+fn pick_many(answer: &str, choices: &[String]) -> Option<Vec<String>> {
+    let mut picked = Vec::new();
+    for token in answer.split(|c: char| c == ',' || c.is_whitespace()) {
+        if token.is_empty() {
+            continue;
+        }
+
+        let choice = match token.parse::<usize>() {
+            Ok(n) if n >= 1 && n <= choices.len() => choices[n - 1].clone(),
+            _ if choices.iter().any(|c| c == token) => token.to_string(),
+            _ => return None,
+        };
+        if !picked.contains(&choice) {
+            picked.push(choice);
+        }
+    }
+    Some(picked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn choices() -> Vec<String> {
+        vec!["master".to_string(), "develop".to_string(), "release/1.0".to_string()]
+    }
+
+    #[test]
+    fn picking_one() {
+        let choices = choices();
+        assert_eq!(None, pick("", &choices));
+        assert_eq!(Some("develop".to_string()), pick("2", &choices));
+        assert_eq!(Some("hotfix".to_string()), pick("hotfix", &choices));
+        // out of range numbers could be branch names
+        assert_eq!(Some("4".to_string()), pick("4", &choices));
+    }
+
+    #[test]
+    fn picking_many() {
+        let choices = choices();
+        assert_eq!(Some(vec![]), pick_many("", &choices));
+        assert_eq!(
+            Some(vec!["master".to_string(), "release/1.0".to_string()]),
+            pick_many("1, 3 1", &choices)
+        );
+        assert_eq!(Some(vec!["develop".to_string()]), pick_many("develop", &choices));
+        assert_eq!(None, pick_many("1 4", &choices));
+        assert_eq!(None, pick_many("hotfix", &choices));
+    }
+}